@@ -16,6 +16,7 @@ in
       root = ../.;
       fileset = lib.fileset.unions [
         ../src
+        ../data
         ../Cargo.lock
         ../Cargo.toml
       ];
@@ -27,6 +28,11 @@ in
     nativeBuildInputs = [pkg-config];
     buildInputs = [dbus libxcb];
 
+    postInstall = ''
+      install -Dm644 data/icons/hicolor/scalable/status/*.svg \
+        -t $out/share/icons/hicolor/scalable/status
+    '';
+
     meta = {
       description = "Rust implementation of tailscale-systray";
       homepage = "https://github.com/notashelf/tailray";
@@ -0,0 +1,298 @@
+use std::{
+  env,
+  fs,
+  sync::{
+    atomic::{AtomicU8, Ordering},
+    mpsc,
+  },
+  thread,
+  time::Duration,
+};
+
+use futures_lite::StreamExt;
+use log::{debug, info, warn};
+use tokio::runtime;
+use zbus::{
+  Connection,
+  proxy,
+  zvariant::{OwnedValue, Value},
+};
+
+use crate::config;
+
+/// Settings namespace holding the color scheme
+const NAMESPACE: &str = "org.freedesktop.appearance";
+
+/// Key of the color scheme within [`NAMESPACE`]
+const KEY: &str = "color-scheme";
+
+/// How long startup waits for the portal before assuming no preference
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The last known preference, stored as its portal value
+static COLOR_SCHEME: AtomicU8 = AtomicU8::new(ColorScheme::NoPreference as u8);
+
+/// Extracts the preference from the contents of a settings file
+type SettingsParser = fn(&str) -> Option<ColorScheme>;
+
+/// The desktop's light/dark preference, numbered as in the portal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+  NoPreference = 0,
+  Dark         = 1,
+  Light        = 2,
+}
+
+impl ColorScheme {
+  const fn from_portal(value: u32) -> Self {
+    match value {
+      1 => Self::Dark,
+      2 => Self::Light,
+      _ => Self::NoPreference,
+    }
+  }
+
+  /// Extracts the preference from a portal reply, which older portals wrap
+  /// in a second variant
+  fn from_value(value: &Value<'_>) -> Option<Self> {
+    match value {
+      Value::U32(value) => Some(Self::from_portal(*value)),
+      Value::Value(inner) => Self::from_value(inner),
+      _ => None,
+    }
+  }
+}
+
+#[proxy(
+  interface = "org.freedesktop.portal.Settings",
+  default_service = "org.freedesktop.portal.Desktop",
+  default_path = "/org/freedesktop/portal/desktop"
+)]
+trait Settings {
+  /// Reads a single setting, available since version 2 of the interface
+  fn read_one(&self, namespace: &str, key: &str) -> zbus::Result<OwnedValue>;
+
+  /// Reads a single setting, wrapped in an extra variant
+  fn read(&self, namespace: &str, key: &str) -> zbus::Result<OwnedValue>;
+
+  #[zbus(signal)]
+  fn setting_changed(
+    &self,
+    namespace: &str,
+    key: &str,
+    value: Value<'_>,
+  ) -> zbus::Result<()>;
+}
+
+/// Returns the desktop's last known light/dark preference
+pub fn color_scheme() -> ColorScheme {
+  ColorScheme::from_portal(COLOR_SCHEME.load(Ordering::Relaxed).into())
+}
+
+/// Reads the desktop's light/dark preference and keeps it up to date,
+/// calling `on_change` whenever the user switches it
+///
+/// The preference comes from the XDG Desktop Portal, or from the GTK and Qt
+/// settings files when no portal is running, in which case it is only read
+/// once. Returns once the initial preference is known.
+pub fn watch<F>(on_change: F)
+where
+  F: Fn(ColorScheme) + Send + 'static,
+{
+  let (tx, rx) = mpsc::channel();
+  let (changes_tx, changes_rx) = mpsc::channel();
+
+  // Callers may block, which the portal's runtime must not
+  thread::spawn(move || {
+    for scheme in changes_rx {
+      on_change(scheme);
+    }
+  });
+
+  thread::spawn(move || {
+    let runtime =
+      match runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+          warn!("Failed to start the appearance watcher: {e}");
+          set(from_settings_files());
+          let _ = tx.send(());
+          return;
+        },
+      };
+
+    runtime.block_on(async move {
+      if let Err(e) = follow_portal(&tx, &changes_tx).await {
+        debug!("Desktop portal unavailable ({e}), reading settings files");
+        set(from_settings_files());
+        let _ = tx.send(());
+      }
+    });
+  });
+
+  if rx.recv_timeout(READ_TIMEOUT).is_err() {
+    warn!("Timed out reading the desktop's color scheme");
+  }
+  info!("Desktop color scheme: {:?}", color_scheme());
+}
+
+/// Reads the preference from the portal, signals `ready` and then sends
+/// every change to `changes` until the connection closes
+async fn follow_portal(
+  ready: &mpsc::Sender<()>,
+  changes: &mpsc::Sender<ColorScheme>,
+) -> zbus::Result<()> {
+  let connection = Connection::session().await?;
+  let settings = SettingsProxy::new(&connection).await?;
+  let mut signals = settings.receive_setting_changed().await?;
+
+  let value = match settings.read_one(NAMESPACE, KEY).await {
+    Ok(value) => value,
+    Err(e) => {
+      debug!("ReadOne failed ({e}), falling back to Read");
+      settings.read(NAMESPACE, KEY).await?
+    },
+  };
+  set(ColorScheme::from_value(&value).unwrap_or(ColorScheme::NoPreference));
+  let _ = ready.send(());
+
+  while let Some(signal) = signals.next().await {
+    let Ok(args) = signal.args() else {
+      continue;
+    };
+    if args.namespace != NAMESPACE || args.key != KEY {
+      continue;
+    }
+
+    if let Some(scheme) = ColorScheme::from_value(&args.value)
+      && scheme != color_scheme()
+    {
+      info!("Desktop color scheme changed to {scheme:?}");
+      set(scheme);
+      let _ = changes.send(scheme);
+    }
+  }
+
+  Ok(())
+}
+
+fn set(scheme: ColorScheme) {
+  COLOR_SCHEME.store(scheme as u8, Ordering::Relaxed);
+}
+
+/// Guesses the preference from `GTK_THEME` and the GTK, KDE and qtct
+/// settings files, in that order
+fn from_settings_files() -> ColorScheme {
+  if let Ok(theme) = env::var("GTK_THEME") {
+    return theme_name_scheme(&theme);
+  }
+
+  let Some(config_home) = config::config_home() else {
+    return ColorScheme::NoPreference;
+  };
+
+  let sources: [(&str, SettingsParser); 5] = [
+    ("gtk-4.0/settings.ini", gtk_scheme),
+    ("gtk-3.0/settings.ini", gtk_scheme),
+    ("kdeglobals", kde_scheme),
+    ("qt6ct/qt6ct.conf", qtct_scheme),
+    ("qt5ct/qt5ct.conf", qtct_scheme),
+  ];
+
+  sources
+    .into_iter()
+    .find_map(|(file, parse)| {
+      let contents = fs::read_to_string(config_home.join(file)).ok()?;
+      let scheme = parse(&contents)?;
+      debug!("Read color scheme {scheme:?} from {file}");
+      Some(scheme)
+    })
+    .unwrap_or(ColorScheme::NoPreference)
+}
+
+/// Reads a GTK `settings.ini`
+fn gtk_scheme(contents: &str) -> Option<ColorScheme> {
+  match ini_value(contents, "gtk-application-prefer-dark-theme") {
+    Some("1" | "true") => Some(ColorScheme::Dark),
+    _ => ini_value(contents, "gtk-theme-name").map(theme_name_scheme),
+  }
+}
+
+/// Reads KDE's `kdeglobals`
+fn kde_scheme(contents: &str) -> Option<ColorScheme> {
+  ini_value(contents, "ColorScheme").map(theme_name_scheme)
+}
+
+/// Reads a qt5ct or qt6ct configuration
+fn qtct_scheme(contents: &str) -> Option<ColorScheme> {
+  ini_value(contents, "color_scheme_path").map(theme_name_scheme)
+}
+
+/// Treats themes and color schemes named like "Adwaita:dark" or
+/// "BreezeDark" as dark
+fn theme_name_scheme(name: &str) -> ColorScheme {
+  if name.to_ascii_lowercase().contains("dark") {
+    ColorScheme::Dark
+  } else {
+    ColorScheme::Light
+  }
+}
+
+/// Returns the value of the first `key=value` line for `key`, in any section
+fn ini_value<'a>(contents: &'a str, key: &str) -> Option<&'a str> {
+  contents.lines().find_map(|line| {
+    let (name, value) = line.split_once('=')?;
+    (name.trim() == key).then(|| value.trim())
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn settings_files_are_read() {
+    let gtk = "[Settings]
+gtk-theme-name=Adwaita
+gtk-application-prefer-dark-theme=1
+";
+    assert_eq!(gtk_scheme(gtk), Some(ColorScheme::Dark));
+    assert_eq!(
+      gtk_scheme("[Settings]\ngtk-theme-name = Arc-Dark\n"),
+      Some(ColorScheme::Dark)
+    );
+    assert_eq!(
+      gtk_scheme("[Settings]\ngtk-theme-name=Adwaita\n"),
+      Some(ColorScheme::Light)
+    );
+    assert_eq!(gtk_scheme("[Settings]\n"), None);
+
+    assert_eq!(
+      kde_scheme("[General]\nColorScheme=BreezeDark\n"),
+      Some(ColorScheme::Dark)
+    );
+    assert_eq!(
+      qtct_scheme(
+        "[Appearance]\ncolor_scheme_path=/usr/share/qt6ct/colors/simple.conf\n"
+      ),
+      Some(ColorScheme::Light)
+    );
+  }
+
+  #[test]
+  fn portal_values_are_unwrapped() {
+    assert_eq!(
+      ColorScheme::from_value(&Value::U32(1)),
+      Some(ColorScheme::Dark)
+    );
+    assert_eq!(
+      ColorScheme::from_value(&Value::Value(Box::new(Value::U32(2)))),
+      Some(ColorScheme::Light)
+    );
+    assert_eq!(
+      ColorScheme::from_value(&Value::U32(7)),
+      Some(ColorScheme::NoPreference)
+    );
+    assert_eq!(ColorScheme::from_value(&Value::Bool(true)), None);
+  }
+}
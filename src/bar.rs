@@ -0,0 +1,240 @@
+use std::{
+  io::{self, BufRead, Write},
+  ops::ControlFlow,
+  sync::mpsc,
+  thread,
+};
+
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::{
+  cli::{self, StatusSummary},
+  config,
+  error::AppError,
+  tailscale::{
+    localapi::LocalApi,
+    status::{self, Status},
+  },
+  tray::utils::{for_each_burst, jitter_source, watch_ipn_bus},
+};
+
+/// Output formats understood by status bars
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarFormat {
+  /// One JSON object per line, for a custom module with `return-type: json`
+  Waybar,
+  /// One line of text per update, for a block with `interval=persist`
+  I3blocks,
+  /// One line of text per update, for a script module with `tail = true`
+  Polybar,
+}
+
+/// Connection state, used as CSS class and icon selector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BarClass {
+  Connected,
+  ExitNode,
+  Disconnected,
+  NeedsLogin,
+}
+
+impl BarClass {
+  fn new(status: &Status) -> Self {
+    match status.backend_state() {
+      "NeedsLogin" | "NeedsMachineAuth" => Self::NeedsLogin,
+      _ if !status.tailscale_up => Self::Disconnected,
+      _ if status.exit_node().is_some() => Self::ExitNode,
+      _ => Self::Connected,
+    }
+  }
+
+  const fn as_str(self) -> &'static str {
+    match self {
+      Self::Connected => "connected",
+      Self::ExitNode => "exit-node",
+      Self::Disconnected => "disconnected",
+      Self::NeedsLogin => "needs-login",
+    }
+  }
+}
+
+/// What the bar module shows for a single state
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Module {
+  text:    String,
+  tooltip: String,
+  class:   &'static str,
+  alt:     &'static str,
+}
+
+impl Module {
+  /// Describes `status`, or why it could not be read
+  fn new(status: Result<&Status, &str>) -> Self {
+    let status = match status {
+      Ok(status) => status,
+      Err(e) => {
+        let class = BarClass::Disconnected.as_str();
+        return Self {
+          text: "unavailable".into(),
+          tooltip: e.to_owned(),
+          class,
+          alt: class,
+        };
+      },
+    };
+
+    let class = BarClass::new(status);
+    let ip = status
+      .this_machine
+      .ips
+      .first()
+      .map(ToString::to_string)
+      .unwrap_or_default();
+    let text = match (class, status.exit_node()) {
+      (BarClass::ExitNode, Some(exit_node)) => {
+        format!("{ip} via {}", exit_node.display_name)
+      },
+      (BarClass::Connected | BarClass::ExitNode, _) => ip,
+      (BarClass::Disconnected, _) => "off".into(),
+      (BarClass::NeedsLogin, _) => "login required".into(),
+    };
+
+    Self {
+      text,
+      tooltip: StatusSummary::new(status).to_string(),
+      class: class.as_str(),
+      alt: class.as_str(),
+    }
+  }
+
+  /// Renders the line printed for `format`
+  fn line(&self, format: BarFormat) -> String {
+    match format {
+      BarFormat::Waybar => serde_json::to_string(self).unwrap_or_default(),
+      BarFormat::I3blocks | BarFormat::Polybar => self.text.clone(),
+    }
+  }
+}
+
+/// Prints a line for the bar whenever the displayed state changes, until
+/// the bar stops reading
+pub fn run(format: BarFormat) -> Result<(), AppError> {
+  if format == BarFormat::I3blocks {
+    thread::spawn(handle_clicks);
+  }
+
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    watch_ipn_bus(&LocalApi::default(), tx.clone());
+
+    // The bus cannot be watched, so poll instead
+    while tx.send(()).is_ok() {
+      thread::sleep(config::current().refresh.next_delay(0, jitter_source()));
+    }
+  });
+
+  let mut stdout = io::stdout().lock();
+  let mut last_line = String::new();
+  let mut emit = || -> io::Result<()> {
+    let status = status::get().map_err(|e| e.to_string());
+    let line =
+      Module::new(status.as_ref().map_err(String::as_str)).line(format);
+    if line != last_line {
+      debug!("Bar state changed: {line}");
+      writeln!(stdout, "{line}")?;
+      stdout.flush()?;
+      last_line = line;
+    }
+    Ok(())
+  };
+
+  emit()?;
+  let mut result = Ok(());
+  for_each_burst(&rx, || {
+    match emit() {
+      Ok(()) => ControlFlow::Continue(()),
+      Err(e) => {
+        result = Err(e);
+        ControlFlow::Break(())
+      },
+    }
+  });
+
+  match result {
+    // The bar went away, which is how it asks modules to stop
+    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+      info!("Bar closed the output, exiting");
+      Ok(())
+    },
+    result => Ok(result?),
+  }
+}
+
+/// Toggles the connection on left clicks reported by i3blocks on stdin
+fn handle_clicks() {
+  for line in io::stdin().lock().lines() {
+    let Ok(line) = line else {
+      return;
+    };
+
+    if button(&line) == Some(1)
+      && let Err(e) = cli::toggle()
+    {
+      warn!("Failed to toggle Tailscale: {e}");
+    }
+  }
+}
+
+/// Extracts the mouse button from a click event, given either as a JSON
+/// object or as a bare button number
+fn button(event: &str) -> Option<u64> {
+  let event = event.trim();
+  if event.starts_with('{') {
+    serde_json::from_str::<serde_json::Value>(event)
+      .ok()?
+      .get("button")?
+      .as_u64()
+  } else {
+    event.parse().ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STATUS_JSON: &str = include_str!("tailscale/testdata/status.json");
+
+  #[test]
+  fn exit_node_state_is_rendered() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let module = Module::new(Ok(&status));
+
+    assert_eq!(module.text, "100.101.102.103 via nas");
+    assert_eq!(module.class, "exit-node");
+    assert!(module.tooltip.contains("Exit node: nas"));
+    assert_eq!(module.line(BarFormat::Polybar), "100.101.102.103 via nas");
+
+    let json: serde_json::Value =
+      serde_json::from_str(&module.line(BarFormat::Waybar)).unwrap();
+    assert_eq!(json["class"], "exit-node");
+    assert!(json.get("percentage").is_none());
+  }
+
+  #[test]
+  fn unreadable_status_is_rendered_as_disconnected() {
+    let module = Module::new(Err("tailscaled is not running"));
+
+    assert_eq!(module.class, "disconnected");
+    assert_eq!(module.tooltip, "tailscaled is not running");
+  }
+
+  #[test]
+  fn click_buttons_are_parsed() {
+    assert_eq!(button("1"), Some(1));
+    assert_eq!(button(r#"{"name":"tailray","button":3,"x":10}"#), Some(3));
+    assert_eq!(button("garbage"), None);
+  }
+}
@@ -0,0 +1,298 @@
+use std::{
+  error::Error,
+  fmt,
+  io::{self, Read},
+  net::IpAddr,
+  path::PathBuf,
+};
+
+use clap::{Parser, Subcommand};
+use log::{LevelFilter, info};
+use serde::Serialize;
+
+use crate::{
+  bar::{self, BarFormat},
+  clipboard,
+  config::{self, CommandLine},
+  elevation::run_with_elevation,
+  error::AppError,
+  ipc::{self, IpcError, Request},
+  svg::renderer::Theme,
+  tailscale::status::{self, Status},
+};
+
+/// Errors that can occur while running a command from the command line
+#[derive(Debug)]
+pub enum CliError {
+  /// No peer matches the given name or address
+  PeerNotFound(String),
+  /// The peer does not offer to act as an exit node
+  NotAnExitNode(String),
+  /// The device has no Tailscale IP address
+  NoAddress(String),
+  /// Running a tailscale command failed
+  Command(String),
+}
+
+impl fmt::Display for CliError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::PeerNotFound(query) => write!(f, "No peer matches '{query}'"),
+      Self::NotAnExitNode(name) => {
+        write!(f, "{name} does not offer to act as an exit node")
+      },
+      Self::NoAddress(name) => write!(f, "{name} has no Tailscale IP address"),
+      Self::Command(msg) => write!(f, "Command failed: {msg}"),
+    }
+  }
+}
+
+impl Error for CliError {}
+
+/// A system tray for Tailscale that doubles as a command-line client
+///
+/// Without a command, the tray icon is started.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+  /// Configuration file to read instead of ~/.config/tailray/config.toml
+  #[arg(long, global = true, value_name = "PATH")]
+  pub config: Option<PathBuf>,
+
+  /// Log verbosity: off, error, warn, info, debug or trace
+  #[arg(long, global = true, value_name = "LEVEL")]
+  pub log_level: Option<LevelFilter>,
+
+  /// Tray icon theme, light, dark or auto, overriding the configuration
+  #[arg(long, global = true, value_parser = config::parse_value::<Theme>)]
+  pub theme: Option<Theme>,
+
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+impl Cli {
+  /// Returns the options that affect the configuration
+  pub fn command_line(&self) -> CommandLine {
+    CommandLine {
+      config: self.config.clone(),
+      theme:  self.theme,
+    }
+  }
+}
+
+/// Commands that run once and exit instead of starting the tray
+#[derive(Subcommand, Debug)]
+pub enum Command {
+  /// Show the connection status
+  Status {
+    /// Print the status as JSON
+    #[arg(long)]
+    json: bool,
+  },
+  /// Connect to the tailnet
+  Up,
+  /// Disconnect from the tailnet
+  Down,
+  /// Connect when disconnected and disconnect otherwise
+  Toggle,
+  /// Ask the running tray to re-read the status
+  Refresh,
+  /// Ask the running tray to exit
+  Quit,
+  /// Ask the running tray to show its status summary
+  ShowMenu,
+  /// Route traffic through an exit node, or stop using one with "none"
+  ExitNode {
+    /// Name, MagicDNS name or Tailscale IP of the exit node, or "none"
+    name: String,
+  },
+  /// Copy the Tailscale IP of this device, or of a peer, to the clipboard
+  CopyIp {
+    /// Name, MagicDNS name or Tailscale IP of the peer
+    peer: Option<String>,
+  },
+  /// Print the status for a status bar, one line per change
+  Bar {
+    /// Output format of the bar module
+    #[arg(long, value_enum, default_value_t = BarFormat::Waybar)]
+    format: BarFormat,
+  },
+  /// Serve text read from stdin on the clipboard, used by copy-ip
+  #[command(hide = true)]
+  ServeClipboard,
+}
+
+/// Summary of the connection status, as printed by `tailray status`
+#[derive(Serialize, Debug)]
+pub struct StatusSummary {
+  pub state:        String,
+  pub connected:    bool,
+  pub name:         String,
+  pub dns_name:     Option<String>,
+  pub ips:          Vec<IpAddr>,
+  pub exit_node:    Option<String>,
+  pub peers_online: usize,
+  pub peers_total:  usize,
+}
+
+impl StatusSummary {
+  /// Summarizes `status`
+  pub fn new(status: &Status) -> Self {
+    let this = &status.this_machine;
+    Self {
+      state:        status.backend_state().to_owned(),
+      connected:    status.tailscale_up,
+      name:         this.display_name.to_string(),
+      dns_name:     this.magic_dns_name().map(ToOwned::to_owned),
+      ips:          this.ips.clone(),
+      exit_node:    status
+        .exit_node()
+        .map(|peer| peer.display_name.to_string()),
+      peers_online: status.peers.values().filter(|peer| peer.online).count(),
+      peers_total:  status.peers.len(),
+    }
+  }
+}
+
+impl fmt::Display for StatusSummary {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let ips: Vec<_> = self.ips.iter().map(ToString::to_string).collect();
+
+    writeln!(f, "State:     {}", self.state)?;
+    match &self.dns_name {
+      Some(dns_name) => writeln!(f, "Device:    {} ({dns_name})", self.name)?,
+      None => writeln!(f, "Device:    {}", self.name)?,
+    }
+    writeln!(f, "Addresses: {}", ips.join(", "))?;
+    writeln!(
+      f,
+      "Exit node: {}",
+      self.exit_node.as_deref().unwrap_or("none")
+    )?;
+    write!(
+      f,
+      "Peers:     {} of {} online",
+      self.peers_online, self.peers_total
+    )
+  }
+}
+
+/// Runs `command` to completion
+pub fn run(command: Command) -> Result<(), AppError> {
+  match command {
+    Command::Status { json } => print_status(json),
+    Command::Up => service_link("up"),
+    Command::Down => service_link("down"),
+    Command::Toggle => toggle(),
+    Command::Refresh => Ok(ipc::send(Request::Refresh)?),
+    Command::Quit => Ok(ipc::send(Request::Quit)?),
+    Command::ShowMenu => Ok(ipc::send(Request::ShowMenu)?),
+    Command::ExitNode { name } => set_exit_node(&name),
+    Command::CopyIp { peer } => copy_ip(peer.as_deref()),
+    Command::Bar { format } => bar::run(format),
+    Command::ServeClipboard => serve_clipboard(),
+  }
+}
+
+/// Connects when disconnected and disconnects otherwise
+///
+/// The running tray carries this out if there is one, so that its icon
+/// reflects the change right away.
+pub fn toggle() -> Result<(), AppError> {
+  match ipc::send(Request::Toggle) {
+    Err(IpcError::NotRunning) => {},
+    result => return Ok(result?),
+  }
+
+  let verb = if status::get()?.tailscale_up {
+    "down"
+  } else {
+    "up"
+  };
+  tailscale(&[verb])?;
+  Ok(())
+}
+
+/// Prints the connection status, either for humans or as JSON
+fn print_status(json: bool) -> Result<(), AppError> {
+  let summary = StatusSummary::new(&status::get()?);
+  if json {
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+  } else {
+    println!("{summary}");
+  }
+  Ok(())
+}
+
+/// Brings the Tailscale connection up or down
+fn service_link(verb: &str) -> Result<(), AppError> {
+  tailscale(&[verb])?;
+  println!("Tailscale is {verb}");
+  Ok(())
+}
+
+/// Switches to the exit node matching `name`, or stops using one for "none"
+fn set_exit_node(name: &str) -> Result<(), AppError> {
+  if name.eq_ignore_ascii_case("none") {
+    tailscale(&["set", "--exit-node="])?;
+    println!("No longer using an exit node");
+    return Ok(());
+  }
+
+  let status = status::get()?;
+  let peer = status
+    .find_peer(name)
+    .ok_or_else(|| CliError::PeerNotFound(name.to_owned()))?;
+  let peer_name = peer.display_name.to_string();
+  if !peer.exit_node_option {
+    return Err(CliError::NotAnExitNode(peer_name).into());
+  }
+
+  let target = peer
+    .ips
+    .first()
+    .map_or_else(|| peer.address(), ToString::to_string);
+  tailscale(&["set", &format!("--exit-node={target}")])?;
+  println!("Routing traffic through {peer_name}");
+  Ok(())
+}
+
+/// Copies the first Tailscale IP of this device, or of the peer matching
+/// `peer`, to the clipboard and prints it
+fn copy_ip(peer: Option<&str>) -> Result<(), AppError> {
+  let status = status::get()?;
+  let machine = match peer {
+    Some(query) => {
+      status
+        .find_peer(query)
+        .ok_or_else(|| CliError::PeerNotFound(query.to_owned()))?
+    },
+    None => &status.this_machine,
+  };
+
+  let ip = machine
+    .ips
+    .first()
+    .ok_or_else(|| CliError::NoAddress(machine.display_name.to_string()))?
+    .to_string();
+
+  clipboard::copy_detached(&ip)?;
+  info!("Copied {ip} to clipboard");
+  println!("{ip}");
+  Ok(())
+}
+
+/// Keeps the text read from stdin on the clipboard until it is replaced
+fn serve_clipboard() -> Result<(), AppError> {
+  let mut text = String::new();
+  io::stdin().read_to_string(&mut text)?;
+  clipboard::serve(&text)?;
+  Ok(())
+}
+
+/// Runs the tailscale command with `args`, elevating if needed
+fn tailscale(args: &[&str]) -> Result<String, AppError> {
+  run_with_elevation("tailscale", args)
+    .map_err(|e| CliError::Command(e.to_string()).into())
+}
@@ -1,7 +1,48 @@
-use arboard::Clipboard;
+use std::{
+  env,
+  io::{self, Write},
+  process::{Command, Stdio},
+};
+
+use arboard::{Clipboard, SetExtLinux};
+
+use crate::config;
 
 pub fn copy_and_get(text: &str) -> Result<String, arboard::Error> {
   let mut clipboard = Clipboard::new()?;
   clipboard.set_text(text)?;
   clipboard.get_text()
 }
+
+/// Copies `text` from a background process, so that it stays on the
+/// clipboard after a short-lived command exits
+///
+/// The process runs `tailray serve-clipboard` with the same configuration
+/// file and log level, reports errors on the inherited stderr and exits
+/// once another application takes over the clipboard.
+pub fn copy_detached(text: &str) -> io::Result<()> {
+  let mut command = Command::new(env::current_exe()?);
+  // Without a file the defaults apply, which an explicit path would reject
+  if let Some(path) = config::path().filter(|path| path.exists()) {
+    command.arg("--config").arg(path);
+  }
+  let mut child = command
+    .args(["--log-level", log::max_level().as_str()])
+    .arg("serve-clipboard")
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::inherit())
+    .spawn()?;
+
+  child
+    .stdin
+    .take()
+    .ok_or_else(|| io::Error::other("clipboard process has no stdin"))?
+    .write_all(text.as_bytes())
+}
+
+/// Puts `text` on the clipboard and blocks until another application
+/// replaces it
+pub fn serve(text: &str) -> Result<(), arboard::Error> {
+  Clipboard::new()?.set().wait().text(text)
+}
@@ -0,0 +1,722 @@
+use std::{
+  collections::HashMap,
+  env,
+  error::Error,
+  fmt,
+  fs,
+  io,
+  path::{Path, PathBuf},
+  sync::{Arc, OnceLock, PoisonError, RwLock},
+  thread,
+  time::Duration,
+};
+
+use inotify::{EventMask, Inotify, WatchMask};
+use log::{debug, error, info, warn};
+use serde::{
+  Deserialize,
+  Deserializer,
+  de::{DeserializeOwned, IntoDeserializer, value::StrDeserializer},
+};
+
+use crate::{
+  notification::{Category, CategoryConfig, RateLimitConfig},
+  svg::{
+    renderer::{IconState, Theme},
+    symbolic,
+  },
+  tray::{
+    alerts::{ExpiryAlertConfig, PeerAlertConfig},
+    peers::{PeerAction, PeerMenuConfig},
+    tooltip,
+    utils::PollerConfig,
+  },
+};
+
+/// Admin console opened when none is configured
+pub const DEFAULT_ADMIN_URL: &str =
+  "https://login.tailscale.com/admin/machines";
+
+/// Configuration currently in effect
+static CURRENT: OnceLock<RwLock<Arc<Config>>> = OnceLock::new();
+
+/// Options given on the command line, kept for reloading
+static COMMAND_LINE: OnceLock<CommandLine> = OnceLock::new();
+
+/// How long to wait for an editor to finish writing before reloading
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Errors that can occur while loading the configuration
+#[derive(Debug)]
+pub enum ConfigError {
+  /// The configuration file could not be read
+  Read { path: PathBuf, reason: String },
+  /// The configuration file is not valid TOML or does not match the schema
+  Parse { path: PathBuf, message: String },
+  /// A setting has a value outside of its allowed range
+  Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Read { path, reason } => {
+        write!(f, "Failed to read {}: {reason}", path.display())
+      },
+      Self::Parse { path, message } => {
+        write!(f, "Invalid configuration in {}: {message}", path.display())
+      },
+      Self::Invalid(msg) => write!(f, "Invalid configuration: {msg}"),
+    }
+  }
+}
+
+impl Error for ConfigError {}
+
+/// Tool used to run commands that need root privileges
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ElevationMethod {
+  /// Use pkexec if available, otherwise sudo
+  #[default]
+  Auto,
+  /// Only use pkexec
+  Pkexec,
+  /// Only use sudo
+  Sudo,
+  /// Never elevate, failing commands that need root instead
+  Never,
+}
+
+/// Settings for privilege elevation
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ElevationConfig {
+  pub method: ElevationMethod,
+}
+
+/// How the tray icon is handed to the tray host
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconMode {
+  /// Send rendered pixmaps
+  #[default]
+  Pixmap,
+  /// Send icon names for the host to look up, with pixmaps as fallback
+  Name,
+}
+
+/// Settings for the tray icon
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconConfig {
+  /// Directory with one SVG per state replacing the built-in icons
+  pub directory:  Option<PathBuf>,
+  pub mode:       IconMode,
+  /// Icon names replacing the bundled symbolic icons, by state
+  pub names:      HashMap<IconState, String>,
+  /// Directory the host searches for icon names before its icon theme
+  pub theme_path: Option<PathBuf>,
+}
+
+impl IconConfig {
+  /// Returns the icon name used for `state` in [`IconMode::Name`]
+  pub fn name(&self, state: IconState) -> String {
+    self
+      .names
+      .get(&state)
+      .cloned()
+      .unwrap_or_else(|| symbolic::icon_name(state))
+  }
+}
+
+/// Settings for the tray icon's tooltip
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TooltipConfig {
+  /// Description below the title, with `{placeholder}`s filled in
+  pub template: String,
+}
+
+impl Default for TooltipConfig {
+  fn default() -> Self {
+    Self {
+      template: tooltip::DEFAULT_TEMPLATE.to_owned(),
+    }
+  }
+}
+
+/// Settings for desktop notifications
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+  /// Whether to show notifications at all
+  pub enabled:    bool,
+  /// How long notifications stay visible, in milliseconds
+  pub timeout:    u32,
+  /// Settings of individual categories, overriding the defaults
+  pub categories: HashMap<Category, CategoryConfig>,
+  /// Limit on notifications of one category in a short time
+  pub rate_limit: RateLimitConfig,
+  /// Notifications about watched peers changing online state
+  pub peers:      PeerAlertConfig,
+  /// Warnings about node keys that are about to expire
+  pub expiry:     ExpiryAlertConfig,
+}
+
+impl Default for NotificationConfig {
+  fn default() -> Self {
+    Self {
+      enabled:    true,
+      timeout:    3000,
+      categories: HashMap::new(),
+      rate_limit: RateLimitConfig::default(),
+      peers:      PeerAlertConfig::default(),
+      expiry:     ExpiryAlertConfig::default(),
+    }
+  }
+}
+
+impl NotificationConfig {
+  /// Returns the settings of `category`
+  pub fn category(&self, category: Category) -> CategoryConfig {
+    self.categories.get(&category).copied().unwrap_or_default()
+  }
+}
+
+/// Configuration options given on the command line
+#[derive(Debug, Clone, Default)]
+pub struct CommandLine {
+  /// Configuration file to read instead of the default one
+  pub config: Option<PathBuf>,
+  /// Theme taking precedence over both the file and the environment
+  pub theme:  Option<Theme>,
+}
+
+impl CommandLine {
+  /// Loads the configuration these options point at and applies them
+  fn load(&self) -> Result<Config, ConfigError> {
+    let mut config = Config::load(self.config.as_deref())?;
+    if let Some(theme) = self.theme {
+      config.theme = theme;
+    }
+    Ok(config)
+  }
+}
+
+/// The complete Tailray configuration
+///
+/// Loaded from `$XDG_CONFIG_HOME/tailray/config.toml` by default, with
+/// environment variables taking precedence over the file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub theme:         Theme,
+  pub icons:         IconConfig,
+  pub tooltip:       TooltipConfig,
+  pub admin_url:     String,
+  pub refresh:       PollerConfig,
+  pub menu:          PeerMenuConfig,
+  pub notifications: NotificationConfig,
+  pub elevation:     ElevationConfig,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      theme:         Theme::default(),
+      icons:         IconConfig::default(),
+      tooltip:       TooltipConfig::default(),
+      admin_url:     DEFAULT_ADMIN_URL.to_owned(),
+      refresh:       PollerConfig::default(),
+      menu:          PeerMenuConfig::default(),
+      notifications: NotificationConfig::default(),
+      elevation:     ElevationConfig::default(),
+    }
+  }
+}
+
+impl Config {
+  /// Loads the configuration from `path`, or from the default location if
+  /// no path is given, and applies environment overrides
+  ///
+  /// A missing file at the default location is not an error, the defaults
+  /// are used instead.
+  fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+    let mut config = match path {
+      Some(path) => Self::from_file(path)?,
+      None => {
+        match default_path() {
+          Some(path) if path.exists() => Self::from_file(&path)?,
+          _ => {
+            debug!("No configuration file found, using defaults");
+            Self::default()
+          },
+        }
+      },
+    };
+
+    config.apply_env();
+    config.validate()?;
+    Ok(config)
+  }
+
+  /// Reads and parses the configuration file at `path`
+  pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+    info!("Loading configuration from {}", path.display());
+    let contents = fs::read_to_string(path).map_err(|e| {
+      ConfigError::Read {
+        path:   path.to_owned(),
+        reason: e.to_string(),
+      }
+    })?;
+
+    Self::parse(&contents).map_err(|message| {
+      ConfigError::Parse {
+        path: path.to_owned(),
+        message,
+      }
+    })
+  }
+
+  /// Parses a configuration document, describing errors by their location
+  fn parse(contents: &str) -> Result<Self, String> {
+    toml::from_str(contents).map_err(|e| {
+      let Some(span) = e.span() else {
+        return e.message().to_owned();
+      };
+
+      let before = &contents[..span.start.min(contents.len())];
+      let line = before.matches('\n').count() + 1;
+      let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+      format!("line {line}, column {column}: {}", e.message())
+    })
+  }
+
+  /// Overrides settings with the `TAILRAY_*` environment variables
+  fn apply_env(&mut self) {
+    if let Some(theme) = env_value("TAILRAY_THEME") {
+      self.theme = theme;
+    }
+    if let Ok(url) = env::var("TAILRAY_ADMIN_URL") {
+      self.admin_url = url;
+    }
+
+    let refresh = &mut self.refresh;
+    if let Some(interval) = env_seconds("TAILRAY_POLL_INTERVAL") {
+      refresh.interval = interval;
+    }
+    if let Some(jitter) = env_seconds("TAILRAY_POLL_JITTER") {
+      refresh.jitter = jitter;
+    }
+    if let Some(max_backoff) = env_seconds("TAILRAY_POLL_MAX_BACKOFF") {
+      refresh.max_backoff = max_backoff;
+    }
+
+    let menu = &mut self.menu;
+    if let Some(grouping) = env_value("TAILRAY_PEER_GROUPING") {
+      menu.grouping = grouping;
+    }
+    if let Some(sort) = env_value("TAILRAY_PEER_SORT") {
+      menu.sort = sort;
+    }
+    if let Some(offline) = env_value("TAILRAY_OFFLINE_PEERS") {
+      menu.offline = offline;
+    }
+    if let Ok(actions) = env::var("TAILRAY_HIDDEN_PEER_ACTIONS") {
+      menu.hidden_actions = actions
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+          parse_value::<PeerAction>(name)
+            .inspect_err(|e| {
+              warn!("Ignoring peer action in TAILRAY_HIDDEN_PEER_ACTIONS: {e}");
+            })
+            .ok()
+        })
+        .collect();
+    }
+  }
+
+  /// Checks settings that the schema alone cannot express
+  fn validate(&self) -> Result<(), ConfigError> {
+    if !(self.admin_url.starts_with("https://")
+      || self.admin_url.starts_with("http://"))
+    {
+      return Err(ConfigError::Invalid(format!(
+        "admin_url must be an http(s) URL, got '{}'",
+        self.admin_url
+      )));
+    }
+
+    for (key, path) in [
+      ("icons.directory", &self.icons.directory),
+      ("icons.theme_path", &self.icons.theme_path),
+    ] {
+      if let Some(path) = path
+        && !path.is_absolute()
+      {
+        return Err(ConfigError::Invalid(format!(
+          "{key} must be an absolute path, got '{}'",
+          path.display()
+        )));
+      }
+    }
+
+    tooltip::validate(&self.tooltip.template)
+      .map_err(|e| ConfigError::Invalid(format!("tooltip.template: {e}")))?;
+
+    if self.notifications.rate_limit.burst == 0 {
+      return Err(ConfigError::Invalid(
+        "notifications.rate_limit.burst must be greater than zero".into(),
+      ));
+    }
+
+    if self.refresh.interval.is_zero() {
+      return Err(ConfigError::Invalid(
+        "refresh.interval must be greater than zero".into(),
+      ));
+    }
+
+    if self.refresh.max_backoff < self.refresh.interval {
+      return Err(ConfigError::Invalid(
+        "refresh.max_backoff must not be shorter than refresh.interval".into(),
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+/// Loads the configuration selected by `command_line` and makes it the one
+/// in effect
+///
+/// The options are remembered so that [`reload`] and [`watch`] honor them
+/// as well.
+pub fn init(command_line: CommandLine) -> Result<(), ConfigError> {
+  let config = command_line.load()?;
+  let _ = COMMAND_LINE.set(command_line);
+  set_current(config);
+  Ok(())
+}
+
+/// Re-reads the configuration, replacing the one in effect on success
+///
+/// On error the previous configuration stays in effect.
+pub fn reload() -> Result<Arc<Config>, ConfigError> {
+  let config = COMMAND_LINE.get_or_init(CommandLine::default).load()?;
+  set_current(config);
+  Ok(current())
+}
+
+/// Returns the configuration file in use, whether or not it exists
+pub fn path() -> Option<PathBuf> {
+  COMMAND_LINE
+    .get()
+    .and_then(|command_line| command_line.config.clone())
+    .or_else(default_path)
+}
+
+/// Watches the configuration file and reloads it whenever it changes,
+/// passing the outcome of every reload that changed something to `on_reload`
+///
+/// The parent directory is watched rather than the file itself, so that
+/// editors replacing the file on save and files created later are noticed.
+/// It is created first if it does not exist yet.
+pub fn watch<F>(mut on_reload: F) -> io::Result<()>
+where
+  F: FnMut(Result<Arc<Config>, ConfigError>) + Send + 'static,
+{
+  let Some(path) = path() else {
+    return Err(io::Error::new(
+      io::ErrorKind::NotFound,
+      "no configuration path could be determined",
+    ));
+  };
+  let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("{} is not a file path", path.display()),
+    ));
+  };
+
+  // Without the directory there would be nothing to watch
+  fs::create_dir_all(dir)?;
+
+  let mut inotify = Inotify::init()?;
+  inotify.watches().add(
+    dir,
+    WatchMask::CLOSE_WRITE
+      | WatchMask::MOVED_TO
+      | WatchMask::MOVED_FROM
+      | WatchMask::DELETE,
+  )?;
+  info!("Watching {} for configuration changes", path.display());
+
+  let name = name.to_owned();
+  thread::spawn(move || {
+    let mut buffer = [0; 4096];
+    loop {
+      let touched = match inotify.read_events_blocking(&mut buffer) {
+        Ok(events) => {
+          events
+            .filter(|event| !event.mask.contains(EventMask::ISDIR))
+            .any(|event| event.name == Some(name.as_os_str()))
+        },
+        Err(e) => {
+          error!("Stopped watching the configuration file: {e}");
+          return;
+        },
+      };
+      if !touched {
+        continue;
+      }
+
+      // Let editors that write in several steps finish, then drop the
+      // events they caused in the meantime
+      thread::sleep(RELOAD_DEBOUNCE);
+      while inotify
+        .read_events(&mut buffer)
+        .is_ok_and(|mut events| events.next().is_some())
+      {}
+
+      let previous = current();
+      match reload() {
+        Ok(config) if config == previous => {
+          debug!("Configuration file changed, but no setting did");
+        },
+        Ok(config) => {
+          info!("Reloaded configuration");
+          on_reload(Ok(config));
+        },
+        Err(e) => {
+          warn!("Keeping previous configuration: {e}");
+          on_reload(Err(e));
+        },
+      }
+    }
+  });
+
+  Ok(())
+}
+
+/// Returns the configuration currently in effect
+pub fn current() -> Arc<Config> {
+  CURRENT
+    .get()
+    .map(|config| {
+      config
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    })
+    .unwrap_or_default()
+}
+
+/// Replaces the configuration currently in effect
+fn set_current(config: Config) {
+  let config = Arc::new(config);
+  let lock = CURRENT.get_or_init(|| RwLock::new(config.clone()));
+  *lock.write().unwrap_or_else(PoisonError::into_inner) = config;
+}
+
+/// Returns `$XDG_CONFIG_HOME/tailray/config.toml`, falling back to
+/// `~/.config` when `XDG_CONFIG_HOME` is unset
+pub fn default_path() -> Option<PathBuf> {
+  Some(config_home()?.join("tailray").join("config.toml"))
+}
+
+/// Returns `$XDG_CONFIG_HOME`, falling back to `~/.config`
+pub fn config_home() -> Option<PathBuf> {
+  xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Returns `$XDG_DATA_HOME`, falling back to `~/.local/share`
+pub fn data_home() -> Option<PathBuf> {
+  xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// Returns `$XDG_STATE_HOME`, falling back to `~/.local/state`
+pub fn state_home() -> Option<PathBuf> {
+  xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Returns the absolute directory in the environment variable `name`,
+/// falling back to `fallback` within the home directory
+fn xdg_dir(name: &str, fallback: &str) -> Option<PathBuf> {
+  env::var_os(name)
+    .map(PathBuf::from)
+    .filter(|path| path.is_absolute())
+    .or_else(|| {
+      env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback))
+    })
+}
+
+/// Deserializes a duration given in (possibly fractional) seconds
+pub fn deserialize_seconds<'de, D>(
+  deserializer: D,
+) -> Result<Duration, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let secs = f64::deserialize(deserializer)?;
+  Duration::try_from_secs_f64(secs).map_err(|_| {
+    serde::de::Error::custom(format!(
+      "expected a non-negative number of seconds, got {secs}"
+    ))
+  })
+}
+
+/// Parses a single setting value using its configuration file spelling
+pub fn parse_value<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+  let value = value.trim().to_lowercase();
+  let deserializer: StrDeserializer<'_, serde::de::value::Error> =
+    value.as_str().into_deserializer();
+  T::deserialize(deserializer).map_err(|e| e.to_string())
+}
+
+/// Reads and parses the environment variable `name`, warning about and
+/// ignoring invalid values
+fn env_value<T: DeserializeOwned>(name: &str) -> Option<T> {
+  let value = env::var(name).ok()?;
+  parse_value(&value)
+    .inspect_err(|e| warn!("Ignoring invalid {name}='{value}': {e}"))
+    .ok()
+}
+
+/// Reads a duration in seconds from the environment variable `name`
+fn env_seconds(name: &str) -> Option<Duration> {
+  let value = env::var(name).ok()?;
+  value
+    .trim()
+    .parse::<f64>()
+    .ok()
+    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    .or_else(|| {
+      warn!("Ignoring invalid {name}='{value}': expected seconds");
+      None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    notification::Urgency,
+    tray::peers::{OfflinePeers, PeerGrouping},
+  };
+
+  #[test]
+  fn parses_full_config() {
+    let config = Config::parse(
+      r#"
+        theme = "dark"
+        admin_url = "https://headscale.example.com/admin"
+
+        [icons]
+        directory = "/home/user/.local/share/tailray/icons"
+        mode = "name"
+        names = { connected = "tailscale-online" }
+
+        [refresh]
+        interval = 2.5
+        max_backoff = 30
+
+        [menu]
+        grouping = "os"
+        offline_peers = "collapse"
+        hidden_peer_actions = ["copy-ssh", "taildrop"]
+
+        [notifications]
+        enabled = false
+
+        [notifications.peers]
+        watch = ["nas", "build-runner-01"]
+        debounce = 60
+
+        [notifications.expiry]
+        warn_days = [7, 1]
+
+        [notifications.categories]
+        copy = { enabled = false }
+        expiry = { urgency = "critical", timeout = 10000 }
+
+        [notifications.rate_limit]
+        burst = 3
+
+        [elevation]
+        method = "sudo"
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.theme, Theme::Dark);
+    assert_eq!(
+      config.icons.directory.as_deref(),
+      Some(Path::new("/home/user/.local/share/tailray/icons"))
+    );
+    assert_eq!(config.icons.mode, IconMode::Name);
+    assert_eq!(config.icons.name(IconState::Running), "tailscale-online");
+    assert_eq!(
+      config.icons.name(IconState::Stopped),
+      "tailray-disconnected-symbolic"
+    );
+    assert_eq!(config.refresh.interval, Duration::from_millis(2500));
+    assert_eq!(config.refresh.jitter, PollerConfig::default().jitter);
+    assert_eq!(config.menu.grouping, PeerGrouping::Os);
+    assert_eq!(config.menu.offline, OfflinePeers::Collapse);
+    assert_eq!(config.menu.hidden_actions, vec![
+      PeerAction::CopySsh,
+      PeerAction::Taildrop
+    ]);
+    assert!(!config.notifications.enabled);
+    assert_eq!(config.notifications.timeout, 3000);
+    assert_eq!(config.notifications.peers.watch, ["nas", "build-runner-01"]);
+    assert_eq!(config.notifications.peers.debounce, Duration::from_secs(60));
+    assert_eq!(config.notifications.expiry.warn_days, [7, 1]);
+    assert!(!config.notifications.category(Category::Copy).enabled);
+    assert_eq!(
+      config.notifications.category(Category::Expiry),
+      CategoryConfig {
+        enabled: true,
+        urgency: Urgency::Critical,
+        timeout: Some(10000),
+      }
+    );
+    assert!(config.notifications.category(Category::Health).enabled);
+    assert_eq!(config.notifications.rate_limit.burst, 3);
+    assert_eq!(config.elevation.method, ElevationMethod::Sudo);
+    assert!(config.validate().is_ok());
+  }
+
+  #[test]
+  fn rejects_unknown_keys() {
+    let err = Config::parse("[menu]\ngroupng = \"os\"\n").unwrap_err();
+    assert!(err.starts_with("line 2, column 1:"), "{err}");
+    assert!(err.contains("groupng"), "{err}");
+  }
+
+  #[test]
+  fn rejects_invalid_values() {
+    assert!(Config::parse("theme = \"purple\"").is_err());
+
+    let config = Config::parse("admin_url = \"ftp://example.com\"").unwrap();
+    assert!(config.validate().is_err());
+
+    let config = Config::parse("[refresh]\ninterval = 0").unwrap();
+    assert!(config.validate().is_err());
+
+    let config = Config::parse("[icons]\ndirectory = \"icons\"").unwrap();
+    assert!(config.validate().is_err());
+
+    assert!(Config::parse("[icons.names]\nonline = \"tailscale\"").is_err());
+
+    let config = Config::parse("[tooltip]\ntemplate = \"{ip}\"").unwrap();
+    assert!(config.validate().is_err());
+
+    let config =
+      Config::parse("[notifications.rate_limit]\nburst = 0").unwrap();
+    assert!(config.validate().is_err());
+  }
+}
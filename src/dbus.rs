@@ -0,0 +1,291 @@
+use std::{
+  borrow::Cow,
+  collections::HashMap,
+  future,
+  sync::{Arc, OnceLock, mpsc},
+  thread,
+};
+
+use log::{debug, info, warn};
+use tokio::runtime::{self, Handle};
+use zbus::{
+  connection,
+  fdo,
+  interface,
+  object_server::{Interface, InterfaceRef},
+  zvariant::Value,
+};
+
+use crate::tray::menu::Context;
+
+/// Well-known name claimed on the session bus
+pub const BUS_NAME: &str = "dev.notashelf.Tailray";
+
+/// Object implementing the control interface
+pub const OBJECT_PATH: &str = "/dev/notashelf/Tailray";
+
+/// The running service, once [`start`] succeeded
+static SERVICE: OnceLock<Service> = OnceLock::new();
+
+/// Callback carrying out an action requested over D-Bus
+type Handler = Arc<dyn Fn(Action) -> Result<(), String> + Send + Sync>;
+
+/// Actions other applications can request over D-Bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+  Connect,
+  Disconnect,
+  Toggle,
+  /// Use the exit node with the given name or address, or none if empty
+  SetExitNode(String),
+  Refresh,
+}
+
+/// Handles needed to publish changes from outside of the bus thread
+struct Service {
+  runtime:   Handle,
+  interface: InterfaceRef<Control>,
+}
+
+/// The values exposed as D-Bus properties
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct State {
+  backend_state: String,
+  self_ip:       String,
+  exit_node:     String,
+}
+
+impl State {
+  fn new(ctx: &Context) -> Self {
+    Self {
+      backend_state: ctx.status.backend_state().to_owned(),
+      self_ip:       ctx.ip.clone(),
+      exit_node:     ctx
+        .status
+        .exit_node()
+        .map(|peer| peer.display_name.to_string())
+        .unwrap_or_default(),
+    }
+  }
+
+  /// Returns the properties that differ from `previous`, by their D-Bus
+  /// names, with their new values
+  fn changes(&self, previous: &Self) -> HashMap<&'static str, String> {
+    [
+      ("BackendState", &self.backend_state, &previous.backend_state),
+      ("SelfIP", &self.self_ip, &previous.self_ip),
+      ("ExitNode", &self.exit_node, &previous.exit_node),
+    ]
+    .into_iter()
+    .filter(|(_, value, previous)| value != previous)
+    .map(|(name, value, _)| (name, value.clone()))
+    .collect()
+  }
+}
+
+/// The `dev.notashelf.Tailray` interface
+struct Control {
+  state:   State,
+  handler: Handler,
+}
+
+impl Control {
+  /// Runs `action` off the bus thread, since it may wait for a password
+  async fn run(&self, action: Action) -> fdo::Result<()> {
+    let handler = self.handler.clone();
+    tokio::task::spawn_blocking(move || handler(action))
+      .await
+      .map_err(|e| fdo::Error::Failed(e.to_string()))?
+      .map_err(fdo::Error::Failed)
+  }
+}
+
+#[interface(name = "dev.notashelf.Tailray")]
+impl Control {
+  /// Connects to the tailnet
+  async fn connect(&self) -> fdo::Result<()> {
+    self.run(Action::Connect).await
+  }
+
+  /// Disconnects from the tailnet
+  async fn disconnect(&self) -> fdo::Result<()> {
+    self.run(Action::Disconnect).await
+  }
+
+  /// Connects when disconnected and disconnects otherwise
+  async fn toggle(&self) -> fdo::Result<()> {
+    self.run(Action::Toggle).await
+  }
+
+  /// Routes traffic through the named exit node, or stops using one when
+  /// `name` is empty or "none"
+  async fn set_exit_node(&self, name: String) -> fdo::Result<()> {
+    self.run(Action::SetExitNode(name)).await
+  }
+
+  /// Re-reads the status from tailscaled
+  async fn refresh(&self) -> fdo::Result<()> {
+    self.run(Action::Refresh).await
+  }
+
+  /// tailscaled's backend state, such as "Running" or "Stopped"
+  #[zbus(property, name = "BackendState")]
+  fn backend_state(&self) -> String {
+    self.state.backend_state.clone()
+  }
+
+  /// The first Tailscale IP of this device
+  #[zbus(property, name = "SelfIP")]
+  fn self_ip(&self) -> String {
+    self.state.self_ip.clone()
+  }
+
+  /// Name of the exit node in use, empty when there is none
+  #[zbus(property, name = "ExitNode")]
+  fn exit_node(&self) -> String {
+    self.state.exit_node.clone()
+  }
+}
+
+/// Claims [`BUS_NAME`] on the session bus and serves the control interface
+/// on a thread of its own, passing requested actions to `handler`
+pub fn start<F>(ctx: &Context, handler: F) -> Result<(), zbus::Error>
+where
+  F: Fn(Action) -> Result<(), String> + Send + Sync + 'static,
+{
+  let control = Control {
+    state:   State::new(ctx),
+    handler: Arc::new(handler),
+  };
+  let (tx, rx) = mpsc::channel();
+
+  thread::spawn(move || {
+    let runtime =
+      match runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+          let _ = tx.send(Err(e.into()));
+          return;
+        },
+      };
+
+    runtime.block_on(async move {
+      let served = async {
+        let connection = connection::Builder::session()?
+          .name(BUS_NAME)?
+          .serve_at(OBJECT_PATH, control)?
+          .build()
+          .await?;
+        let interface = connection
+          .object_server()
+          .interface::<_, Control>(OBJECT_PATH)
+          .await?;
+        Ok::<_, zbus::Error>((connection, interface))
+      };
+
+      match served.await {
+        Ok((_connection, interface)) => {
+          let _ = tx.send(Ok(Service {
+            runtime: Handle::current(),
+            interface,
+          }));
+          // Keep the connection open for as long as Tailray runs
+          future::pending::<()>().await;
+        },
+        Err(e) => {
+          let _ = tx.send(Err(e));
+        },
+      }
+    });
+  });
+
+  let service = rx.recv().map_err(|_| {
+    zbus::Error::Failure("D-Bus service thread exited".into())
+  })??;
+  let _ = SERVICE.set(service);
+  info!("Serving {BUS_NAME} at {OBJECT_PATH} on the session bus");
+  Ok(())
+}
+
+/// Updates the exposed properties from `ctx`, emitting PropertiesChanged
+/// for the ones that differ
+///
+/// Does nothing unless the service was started.
+pub fn publish(ctx: &Context) {
+  let Some(service) = SERVICE.get() else {
+    return;
+  };
+
+  let state = State::new(ctx);
+  let interface = service.interface.clone();
+  service.runtime.spawn(async move {
+    let mut control = interface.get_mut().await;
+    if control.state == state {
+      return;
+    }
+
+    debug!("Publishing state change over D-Bus: {state:?}");
+    let changes = state.changes(&control.state);
+    control.state = state;
+    let changed = changes
+      .iter()
+      .map(|(&name, value)| (name, Value::from(value.as_str())))
+      .collect();
+
+    // A single signal carries every property that changed
+    if let Err(e) = fdo::Properties::properties_changed(
+      interface.signal_emitter(),
+      Control::name(),
+      changed,
+      Cow::Borrowed(&[]),
+    )
+    .await
+    {
+      warn!("Failed to emit PropertiesChanged: {e}");
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("tailscale/testdata/status.json");
+
+  fn state() -> State {
+    State::new(&Context {
+      ip: "100.101.102.103".into(),
+      status: status::parse(STATUS_JSON).expect("fixture should parse"),
+      ..Context::default()
+    })
+  }
+
+  #[test]
+  fn properties_are_derived_from_the_status() {
+    assert_eq!(state(), State {
+      backend_state: "Running".into(),
+      self_ip:       "100.101.102.103".into(),
+      exit_node:     "nas".into(),
+    });
+    assert_eq!(State::new(&Context::default()), State::default());
+  }
+
+  #[test]
+  fn only_changed_properties_are_published() {
+    assert!(state().changes(&state()).is_empty());
+
+    let stopped = State {
+      backend_state: "Stopped".into(),
+      exit_node: String::new(),
+      ..state()
+    };
+    assert_eq!(
+      stopped.changes(&state()),
+      HashMap::from([
+        ("BackendState", "Stopped".to_owned()),
+        ("ExitNode", String::new()),
+      ])
+    );
+  }
+}
@@ -130,8 +130,9 @@ pub fn check_elevation_required(
 
 /// Checks if a tailscale command requires elevated privileges.
 ///
-/// Tailscale's `up` command may require root access depending on socket
-/// permissions. Other tailscale commands typically don't require elevation.
+/// Tailscale's `up`, `down` and `set` commands may require root access
+/// depending on socket permissions. Other tailscale commands typically don't
+/// require elevation.
 ///
 /// # Arguments
 ///
@@ -160,8 +161,8 @@ fn check_tailscale_elevation(
         Ok(false)
       },
     }
-  } else if args.contains(&"down") {
-    // tailscale down requires root if socket is not accessible
+  } else if args.contains(&"down") || args.contains(&"set") {
+    // tailscale down and set require root if socket is not accessible
     match Platform::current() {
       Platform::Linux => {
         match can_access_tailscale_socket() {
@@ -170,7 +171,7 @@ fn check_tailscale_elevation(
         }
       },
       Platform::MacOS | Platform::Windows | Platform::BSD => {
-        // macOS, Windows, and BSD typically don't need sudo for tailscale down
+        // macOS, Windows, and BSD typically don't need sudo for these either
         Ok(false)
       },
     }
@@ -222,7 +223,7 @@ fn can_access_tailscale_socket() -> Result<bool, ()> {
 ///
 /// The filesystem path to the tailscaled control socket.
 #[cfg(unix)]
-fn tailscale_socket_path() -> std::path::PathBuf {
+pub fn tailscale_socket_path() -> std::path::PathBuf {
   #[cfg(target_os = "linux")]
   {
     std::path::PathBuf::from("/var/run/tailscale/tailscaled.sock")
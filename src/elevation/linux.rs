@@ -1,12 +1,14 @@
 use std::process::{Command, Stdio};
 
 use super::error::ElevationError;
+use crate::config::{self, ElevationMethod};
 
 /// Elevates a command on Linux using the appropriate privilege escalation tool.
 ///
 /// First checks for `pkexec` (Polkit) availability, falling back
 /// to `sudo` if pkexec is not available. Both tools require a graphical session
-/// or TTY for password prompts.
+/// or TTY for password prompts. The `elevation.method` setting can restrict
+/// this to a single tool, or disable elevation altogether.
 ///
 /// # Arguments
 ///
@@ -16,8 +18,10 @@ use super::error::ElevationError;
 /// # Returns
 ///
 /// * `Ok(String)` - Command output on success
-/// * `Err(ElevationError::ElevationToolNotFound)` - Neither pkexec nor sudo
-///   available
+/// * `Err(ElevationError::ElevationToolNotFound)` - None of the allowed tools
+///   are available
+/// * `Err(ElevationError::UnsupportedEnvironment)` - Elevation is disabled in
+///   the configuration
 /// * `Err(ElevationError::ElevationDenied)` - User denied the elevation request
 /// * `Err(ElevationError::ExecutionFailed)` - Failed to spawn the elevation
 ///   helper
@@ -27,19 +31,32 @@ pub fn elevate_command_linux(
   program: &str,
   args: &[&str],
 ) -> Result<String, ElevationError> {
-  // Check for pkexec (Polkit)
-  if which::which("pkexec").is_ok() {
-    return elevate_with_pkexec(program, args);
-  }
+  let method = config::current().elevation.method;
+  let tools: &[&str] = match method {
+    ElevationMethod::Auto => &["pkexec", "sudo"],
+    ElevationMethod::Pkexec => &["pkexec"],
+    ElevationMethod::Sudo => &["sudo"],
+    ElevationMethod::Never => {
+      return Err(ElevationError::UnsupportedEnvironment {
+        platform:   String::from("Linux"),
+        constraint: String::from("elevation disabled in configuration"),
+      });
+    },
+  };
 
-  // Check for sudo
-  if which::which("sudo").is_ok() {
-    return elevate_with_sudo(program, args);
+  // Prefer pkexec (Polkit) over sudo when both are allowed
+  for tool in tools {
+    if which::which(tool).is_ok() {
+      return match *tool {
+        "pkexec" => elevate_with_pkexec(program, args),
+        _ => elevate_with_sudo(program, args),
+      };
+    }
   }
 
   Err(ElevationError::ElevationToolNotFound {
     platform: String::from("Linux"),
-    tools:    vec![String::from("pkexec"), String::from("sudo")],
+    tools:    tools.iter().map(|tool| (*tool).to_owned()).collect(),
   })
 }
 
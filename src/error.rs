@@ -1,15 +1,22 @@
 use std::{error::Error, fmt};
 
 use crate::{
+  cli::CliError,
+  config::ConfigError,
+  ipc::IpcError,
   svg::renderer::RenderError,
-  tailscale::{peer::PeerError, status::StatusError},
+  tailscale::{localapi::LocalApiError, peer::PeerError, status::StatusError},
   tray::menu::TrayError,
 };
 
 #[derive(Debug)]
 pub enum AppError {
   // Subsystem errors
+  Cli(CliError),
+  Config(ConfigError),
+  Ipc(IpcError),
   Render(RenderError),
+  LocalApi(LocalApiError),
   Peer(PeerError),
   Status(StatusError),
   Tray(TrayError),
@@ -28,7 +35,11 @@ pub enum AppError {
 impl fmt::Display for AppError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
+      AppError::Cli(e) => write!(f, "{e}"),
+      AppError::Config(e) => write!(f, "Configuration error: {e}"),
+      AppError::Ipc(e) => write!(f, "{e}"),
       AppError::Render(e) => write!(f, "SVG render error: {e}"),
+      AppError::LocalApi(e) => write!(f, "LocalAPI error: {e}"),
       AppError::Peer(e) => write!(f, "Peer error: {e}"),
       AppError::Status(e) => write!(f, "Tailscale status error: {e}"),
       AppError::Tray(e) => write!(f, "Tray error: {e}"),
@@ -45,7 +56,11 @@ impl fmt::Display for AppError {
 impl Error for AppError {
   fn source(&self) -> Option<&(dyn Error + 'static)> {
     match self {
+      AppError::Cli(e) => Some(e),
+      AppError::Config(e) => Some(e),
+      AppError::Ipc(e) => Some(e),
       AppError::Render(e) => Some(e),
+      AppError::LocalApi(e) => Some(e),
       AppError::Peer(e) => Some(e),
       AppError::Status(e) => Some(e),
       AppError::Tray(e) => Some(e),
@@ -60,11 +75,31 @@ impl Error for AppError {
 }
 
 // From conversions for subsystem errors
+impl From<CliError> for AppError {
+  fn from(e: CliError) -> Self {
+    AppError::Cli(e)
+  }
+}
+impl From<ConfigError> for AppError {
+  fn from(e: ConfigError) -> Self {
+    AppError::Config(e)
+  }
+}
+impl From<IpcError> for AppError {
+  fn from(e: IpcError) -> Self {
+    AppError::Ipc(e)
+  }
+}
 impl From<RenderError> for AppError {
   fn from(e: RenderError) -> Self {
     AppError::Render(e)
   }
 }
+impl From<LocalApiError> for AppError {
+  fn from(e: LocalApiError) -> Self {
+    AppError::LocalApi(e)
+  }
+}
 impl From<PeerError> for AppError {
   fn from(e: PeerError) -> Self {
     AppError::Peer(e)
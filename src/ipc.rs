@@ -0,0 +1,271 @@
+use std::{
+  env,
+  error::Error,
+  fmt,
+  fs::{self, File, OpenOptions, Permissions, TryLockError},
+  io::{self, BufRead, BufReader, Write},
+  os::unix::{
+    fs::PermissionsExt,
+    net::{UnixListener, UnixStream},
+  },
+  path::PathBuf,
+  thread,
+  time::Duration,
+};
+
+use log::{debug, info, warn};
+
+/// How long a client waits for the running instance to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the running instance waits for a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Errors that can occur while talking to the running instance
+#[derive(Debug)]
+pub enum IpcError {
+  /// Another instance already holds the lock
+  AlreadyRunning,
+  /// No instance is listening on the socket
+  NotRunning,
+  /// The running instance could not carry out the request
+  Rejected(String),
+  /// Reading or writing the lock or socket failed
+  Io(String),
+}
+
+impl fmt::Display for IpcError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::AlreadyRunning => write!(f, "Tailray is already running"),
+      Self::NotRunning => write!(f, "Tailray is not running"),
+      Self::Rejected(msg) => write!(f, "Running instance failed: {msg}"),
+      Self::Io(msg) => write!(f, "IPC error: {msg}"),
+    }
+  }
+}
+
+impl Error for IpcError {}
+
+impl From<io::Error> for IpcError {
+  fn from(e: io::Error) -> Self {
+    Self::Io(e.to_string())
+  }
+}
+
+/// Requests a second invocation can forward to the running tray
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+  /// Connect when disconnected and disconnect otherwise
+  Toggle,
+  /// Re-read the status and redraw the tray
+  Refresh,
+  /// Exit the tray
+  Quit,
+  /// Show what the tray menu shows
+  ShowMenu,
+}
+
+impl Request {
+  /// Returns the name used on the wire
+  pub const fn as_str(self) -> &'static str {
+    match self {
+      Self::Toggle => "toggle",
+      Self::Refresh => "refresh",
+      Self::Quit => "quit",
+      Self::ShowMenu => "show-menu",
+    }
+  }
+
+  /// Parses a request name as sent by [`send`]
+  pub fn parse(name: &str) -> Option<Self> {
+    [Self::Toggle, Self::Refresh, Self::Quit, Self::ShowMenu]
+      .into_iter()
+      .find(|request| request.as_str() == name)
+  }
+}
+
+/// Per-user lock held by the instance that runs the tray
+///
+/// The socket is removed again when the lock is dropped.
+#[derive(Debug)]
+pub struct InstanceLock {
+  _file: File,
+}
+
+impl InstanceLock {
+  /// Takes the lock, failing with [`IpcError::AlreadyRunning`] if another
+  /// instance holds it
+  pub fn acquire() -> Result<Self, IpcError> {
+    let path = runtime_file("lock");
+    let file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(&path)?;
+
+    match file.try_lock() {
+      Ok(()) => {
+        debug!("Acquired instance lock {}", path.display());
+        Ok(Self { _file: file })
+      },
+      Err(TryLockError::WouldBlock) => Err(IpcError::AlreadyRunning),
+      Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+  }
+
+  /// Listens on the socket and passes every request to `handler`, whose
+  /// error is reported back to the client
+  pub fn serve<F>(&self, handler: F) -> Result<(), IpcError>
+  where
+    F: Fn(Request) -> Result<(), String> + Send + 'static,
+  {
+    // Holding the lock means any existing socket is left over from a crash
+    let path = socket_path();
+    if path.exists() {
+      fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    info!("Listening for commands on {}", path.display());
+
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        match stream {
+          Ok(stream) => {
+            // Clients are answered one at a time, so a silent one must not
+            // hold up the rest
+            let answered = stream
+              .set_read_timeout(Some(REQUEST_TIMEOUT))
+              .and_then(|()| answer(stream, &handler));
+            if let Err(e) = answered {
+              warn!("Failed to answer IPC client: {e}");
+            }
+          },
+          Err(e) => warn!("Failed to accept IPC client: {e}"),
+        }
+      }
+    });
+
+    Ok(())
+  }
+}
+
+impl Drop for InstanceLock {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(socket_path());
+  }
+}
+
+/// Forwards `request` to the running instance and waits for it to be done
+pub fn send(request: Request) -> Result<(), IpcError> {
+  let mut stream = UnixStream::connect(socket_path()).map_err(|e| {
+    match e.kind() {
+      io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => {
+        IpcError::NotRunning
+      },
+      _ => e.into(),
+    }
+  })?;
+  stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+  writeln!(stream, "{}", request.as_str())?;
+
+  let mut reply = String::new();
+  BufReader::new(stream).read_line(&mut reply)?;
+  match reply.trim_end() {
+    "ok" => Ok(()),
+    reply => {
+      let msg = reply.strip_prefix("error: ").unwrap_or(reply);
+      Err(IpcError::Rejected(msg.to_owned()))
+    },
+  }
+}
+
+/// Reads a single request from `stream` and writes back the outcome
+fn answer<F>(stream: UnixStream, handler: &F) -> io::Result<()>
+where
+  F: Fn(Request) -> Result<(), String>,
+{
+  let mut line = String::new();
+  BufReader::new(&stream).read_line(&mut line)?;
+
+  let name = line.trim();
+  let outcome = match Request::parse(name) {
+    Some(request) => {
+      debug!("Received IPC request: {name}");
+      handler(request)
+    },
+    None => Err(format!("unknown request '{name}'")),
+  };
+
+  let mut stream = &stream;
+  match outcome {
+    Ok(()) => writeln!(stream, "ok"),
+    Err(e) => writeln!(stream, "error: {e}"),
+  }
+}
+
+/// Returns the path of the socket the running instance listens on
+fn socket_path() -> PathBuf {
+  runtime_file("sock")
+}
+
+/// Returns the path of a per-user runtime file with the given extension,
+/// placed in `$XDG_RUNTIME_DIR` or, failing that, the temporary directory
+fn runtime_file(extension: &str) -> PathBuf {
+  let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+    .map(PathBuf::from)
+    .filter(|path| path.is_absolute());
+
+  match runtime_dir {
+    Some(dir) => dir.join(format!("tailray.{extension}")),
+    // The temporary directory is shared, so keep users apart
+    None => {
+      let user = whoami::username().unwrap_or_else(|_| "unknown".into());
+      env::temp_dir().join(format!("tailray-{user}.{extension}"))
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+
+  use super::*;
+
+  fn exchange(line: &str) -> String {
+    let (mut client, server) = UnixStream::pair().unwrap();
+    writeln!(client, "{line}").unwrap();
+    answer(server, &|request| {
+      match request {
+        Request::Quit => Ok(()),
+        _ => Err(format!("cannot {}", request.as_str())),
+      }
+    })
+    .unwrap();
+
+    let mut reply = String::new();
+    client.read_to_string(&mut reply).unwrap();
+    reply
+  }
+
+  #[test]
+  fn requests_round_trip() {
+    for request in [
+      Request::Toggle,
+      Request::Refresh,
+      Request::Quit,
+      Request::ShowMenu,
+    ] {
+      assert_eq!(Request::parse(request.as_str()), Some(request));
+    }
+  }
+
+  #[test]
+  fn handler_outcome_is_sent_back() {
+    assert_eq!(exchange("quit"), "ok\n");
+    assert_eq!(exchange("refresh"), "error: cannot refresh\n");
+    assert_eq!(exchange("reboot"), "error: unknown request 'reboot'\n");
+  }
+}
@@ -1,27 +1,75 @@
+mod appearance;
+mod bar;
+mod cli;
 mod clipboard;
+mod config;
+mod dbus;
 mod elevation;
 mod error;
+mod ipc;
+mod notification;
 mod svg;
 mod tailscale;
 mod tray;
 
 use std::process::exit;
 
-use log::{error, info};
+use clap::Parser;
+use log::{LevelFilter, error, info};
 
-use crate::tray::utils::start_tray_service;
+use crate::{
+  cli::Cli,
+  ipc::{InstanceLock, IpcError},
+  tray::utils::start_tray_service,
+};
 
 fn main() {
+  let cli = Cli::parse();
+
+  // Commands print their own output, so keep the log quiet for them
+  let default_level = if cli.command.is_some() {
+    LevelFilter::Warn
+  } else {
+    LevelFilter::Info
+  };
+
   // Initialize logger with reasonable defaults
   env_logger::builder()
-    .filter_level(log::LevelFilter::Info)
+    .filter_level(cli.log_level.unwrap_or(default_level))
     .format_timestamp(Some(env_logger::TimestampPrecision::Seconds))
     .init();
 
+  // Load the configuration before anything reads it
+  if let Err(e) = config::init(cli.command_line()) {
+    error!("{e}");
+    exit(1);
+  }
+
+  if let Some(command) = cli.command {
+    if let Err(e) = cli::run(command) {
+      eprintln!("tailray: {e}");
+      exit(1);
+    }
+    return;
+  }
+
+  // Only one tray per user, later invocations talk to it instead
+  let lock = match InstanceLock::acquire() {
+    Ok(lock) => lock,
+    Err(IpcError::AlreadyRunning) => {
+      info!("Tailray is already running, not starting another tray");
+      return;
+    },
+    Err(e) => {
+      error!("Failed to acquire the instance lock: {e}");
+      exit(1);
+    },
+  };
+
   info!("Starting Tailray application");
 
   // Start tray service
-  if let Err(e) = start_tray_service() {
+  if let Err(e) = start_tray_service(lock) {
     error!("Tray service error: {e}");
     exit(1);
   }
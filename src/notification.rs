@@ -0,0 +1,374 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::{
+    Arc,
+    LazyLock,
+    Mutex,
+    OnceLock,
+    PoisonError,
+    atomic::{AtomicBool, Ordering},
+    mpsc,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use notify_rust::{Notification, Timeout, error::Error};
+use serde::Deserialize;
+
+use crate::config::{self, NotificationConfig, deserialize_seconds};
+
+/// Callback carrying out the action behind a clicked button
+type Handler = Arc<dyn Fn(Action) + Send + Sync>;
+
+/// Where clicked buttons are routed, once [`set_handler`] was called
+static HANDLER: OnceLock<Handler> = OnceLock::new();
+
+/// Whether only critical notifications are shown
+static DO_NOT_DISTURB: AtomicBool = AtomicBool::new(false);
+
+/// Recently shown notifications, for rate limiting
+static LIMITER: LazyLock<Mutex<RateLimiter>> = LazyLock::new(Mutex::default);
+
+/// Kinds of events notifications are shown for, each with its own settings
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+  /// Connecting, disconnecting and changing settings such as the exit node
+  Connection,
+  /// Text copied to the clipboard
+  Copy,
+  /// Peers coming online or going offline, pings and peer details
+  Peer,
+  /// tailscaled's health warnings
+  Health,
+  /// Expiring node keys and re-authentication
+  Expiry,
+  /// Files sent via Taildrop
+  Taildrop,
+  /// Problems with the configuration file
+  Config,
+}
+
+/// How urgently a notification asks for attention
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Urgency {
+  Low,
+  #[default]
+  Normal,
+  /// Shown even in "Do not disturb" mode
+  Critical,
+}
+
+impl From<Urgency> for notify_rust::Urgency {
+  fn from(urgency: Urgency) -> Self {
+    match urgency {
+      Urgency::Low => Self::Low,
+      Urgency::Normal => Self::Normal,
+      Urgency::Critical => Self::Critical,
+    }
+  }
+}
+
+/// Settings for the notifications of one [`Category`]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CategoryConfig {
+  /// Whether to show notifications of this category
+  pub enabled: bool,
+  /// Urgency hint given to the notification daemon
+  pub urgency: Urgency,
+  /// How long notifications stay visible, in milliseconds, instead of the
+  /// global timeout
+  pub timeout: Option<u32>,
+}
+
+impl Default for CategoryConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      urgency: Urgency::default(),
+      timeout: None,
+    }
+  }
+}
+
+/// Limits how many notifications of a category are shown in a while
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+  /// Notifications of one category allowed within `interval`
+  pub burst:    usize,
+  /// Length of the window the burst is counted in
+  #[serde(deserialize_with = "deserialize_seconds")]
+  pub interval: Duration,
+}
+
+impl Default for RateLimitConfig {
+  fn default() -> Self {
+    Self {
+      burst:    5,
+      interval: Duration::from_secs(60),
+    }
+  }
+}
+
+/// Actions offered as buttons on notifications
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+  /// Run `tailscale up` or `tailscale down`, given as the verb
+  ServiceLink(String),
+  /// Show the given error together with tailscaled's recent log
+  ShowLog(String),
+  /// Start an interactive login to renew this device's key
+  Reauthenticate,
+}
+
+/// Sliding-window counter of the notifications shown per category
+#[derive(Debug, Default)]
+struct RateLimiter {
+  shown: HashMap<Category, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+  /// Records a notification of `category` at `now`, unless `config` says
+  /// too many were shown recently
+  fn allow(
+    &mut self,
+    category: Category,
+    config: &RateLimitConfig,
+    now: Instant,
+  ) -> bool {
+    let shown = self.shown.entry(category).or_default();
+    while shown
+      .front()
+      .is_some_and(|&at| now.duration_since(at) >= config.interval)
+    {
+      shown.pop_front();
+    }
+
+    if shown.len() >= config.burst {
+      return false;
+    }
+    shown.push_back(now);
+    true
+  }
+}
+
+/// Routes the buttons clicked on notifications shown from now on to
+/// `handler`, which runs on a background thread
+pub fn set_handler<F>(handler: F)
+where
+  F: Fn(Action) + Send + Sync + 'static,
+{
+  if HANDLER.set(Arc::new(handler)).is_err() {
+    warn!("Notification action handler already set");
+  }
+}
+
+/// Returns whether only critical notifications are shown
+pub fn do_not_disturb() -> bool {
+  DO_NOT_DISTURB.load(Ordering::Relaxed)
+}
+
+/// Turns "Do not disturb" mode on or off
+pub fn set_do_not_disturb(enabled: bool) {
+  info!(
+    "Do not disturb {}",
+    if enabled { "enabled" } else { "disabled" }
+  );
+  DO_NOT_DISTURB.store(enabled, Ordering::Relaxed);
+}
+
+/// Shows a desktop notification of `category`
+///
+/// Does nothing when the configuration, "Do not disturb" mode or the rate
+/// limit hold it back.
+pub fn show(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+) -> Result<(), Error> {
+  show_for(category, summary, body, icon, 0)
+}
+
+/// Shows a desktop notification that stays visible for at least
+/// `timeout_ms`, or the global timeout if that is longer
+///
+/// A timeout configured for `category` takes precedence over both.
+pub fn show_for(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+  timeout_ms: u32,
+) -> Result<(), Error> {
+  let Some(notification) = build(category, summary, body, icon, timeout_ms)
+  else {
+    return Ok(());
+  };
+  notification.show().map(|_| ())
+}
+
+/// Shows a desktop notification with a button for each of `actions`, given
+/// with their labels
+///
+/// Without a handler to carry them out, the buttons are left out.
+pub fn show_with_actions(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+  actions: Vec<(Action, &str)>,
+) -> Result<(), Error> {
+  let Some(handler) = HANDLER.get().cloned() else {
+    return show(category, summary, body, icon);
+  };
+  let Some(mut notification) = build(category, summary, body, icon, 0) else {
+    return Ok(());
+  };
+
+  let mut actions: Vec<_> = actions
+    .into_iter()
+    .enumerate()
+    .map(|(index, (action, label))| {
+      let id = index.to_string();
+      notification.action(&id, label);
+      (id, action)
+    })
+    .collect();
+
+  // The handle is tied to the thread that showed the notification
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    match notification.show() {
+      Ok(handle) => {
+        let _ = tx.send(Ok(()));
+        handle.wait_for_action(|clicked| {
+          if let Some(index) = actions.iter().position(|(id, _)| id == clicked)
+          {
+            let (_, action) = actions.swap_remove(index);
+            debug!("Notification action clicked: {action:?}");
+            handler(action);
+          }
+        });
+      },
+      Err(e) => {
+        let _ = tx.send(Err(e));
+      },
+    }
+  });
+  rx.recv().unwrap_or(Ok(()))
+}
+
+/// Prepares a notification with the settings of `category`, or returns
+/// `None` when it should not be shown
+fn build(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+  timeout_ms: u32,
+) -> Option<Notification> {
+  let config = config::current();
+  let settings = config.notifications.category(category);
+  if !is_wanted(&config.notifications, category, do_not_disturb()) {
+    debug!("{category:?} notifications disabled, not showing '{summary}'");
+    return None;
+  }
+
+  if !LIMITER
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+    .allow(category, &config.notifications.rate_limit, Instant::now())
+  {
+    info!("Too many {category:?} notifications, not showing '{summary}'");
+    return None;
+  }
+
+  let timeout = timeout(&settings, config.notifications.timeout, timeout_ms);
+  let mut notification = Notification::new();
+  notification
+    .summary(summary)
+    .body(body)
+    .icon(icon)
+    .urgency(settings.urgency.into())
+    .timeout(Timeout::Milliseconds(timeout));
+  Some(notification)
+}
+
+/// Returns how long a notification asking for at least `requested` stays
+/// visible, which the category's timeout overrides
+fn timeout(settings: &CategoryConfig, global: u32, requested: u32) -> u32 {
+  settings.timeout.unwrap_or_else(|| requested.max(global))
+}
+
+/// Returns whether `config` lets notifications of `category` through, with
+/// only critical ones passing in "Do not disturb" mode
+fn is_wanted(
+  config: &NotificationConfig,
+  category: Category,
+  do_not_disturb: bool,
+) -> bool {
+  let settings = config.category(category);
+  config.enabled
+    && settings.enabled
+    && (!do_not_disturb || settings.urgency == Urgency::Critical)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bursts_are_limited_per_category() {
+    let config = RateLimitConfig {
+      burst:    2,
+      interval: Duration::from_secs(10),
+    };
+    let start = Instant::now();
+    let mut limiter = RateLimiter::default();
+
+    assert!(limiter.allow(Category::Connection, &config, start));
+    assert!(limiter.allow(Category::Connection, &config, start));
+    assert!(!limiter.allow(Category::Connection, &config, start));
+    assert!(limiter.allow(Category::Health, &config, start));
+
+    let later = start + Duration::from_secs(10);
+    assert!(limiter.allow(Category::Connection, &config, later));
+  }
+
+  #[test]
+  fn category_timeout_replaces_requested_one() {
+    let unset = CategoryConfig::default();
+    assert_eq!(timeout(&unset, 3000, 0), 3000);
+    assert_eq!(timeout(&unset, 3000, 10000), 10000);
+
+    let short = CategoryConfig {
+      timeout: Some(1000),
+      ..CategoryConfig::default()
+    };
+    assert_eq!(timeout(&short, 3000, 10000), 1000);
+  }
+
+  #[test]
+  fn do_not_disturb_lets_critical_notifications_through() {
+    let mut config = NotificationConfig::default();
+    config.categories.insert(Category::Expiry, CategoryConfig {
+      urgency: Urgency::Critical,
+      ..CategoryConfig::default()
+    });
+    config.categories.insert(Category::Copy, CategoryConfig {
+      enabled: false,
+      ..CategoryConfig::default()
+    });
+
+    assert!(is_wanted(&config, Category::Connection, false));
+    assert!(!is_wanted(&config, Category::Connection, true));
+    assert!(is_wanted(&config, Category::Expiry, true));
+    assert!(!is_wanted(&config, Category::Copy, false));
+  }
+}
@@ -0,0 +1,224 @@
+use std::{
+  collections::HashMap,
+  fs,
+  io,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, PoisonError, RwLock},
+  thread,
+  time::Duration,
+};
+
+use inotify::{Inotify, WatchDescriptor, WatchMask, Watches};
+use log::{debug, error, info, warn};
+use resvg::usvg::{Options, Tree};
+
+use crate::{
+  config,
+  svg::renderer::{IconState, Resvg},
+};
+
+/// How long to wait for a burst of file changes to settle
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Icons currently in effect
+static CURRENT: RwLock<Option<Arc<UserIcons>>> = RwLock::new(None);
+
+/// The inotify watch on the icon directory, once [`watch`] was called
+static WATCHER: Mutex<Option<Watcher>> = Mutex::new(None);
+
+/// SVGs from the configured icon directory, replacing the built-in icons for
+/// the states they cover
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UserIcons {
+  directory: Option<PathBuf>,
+  icons:     HashMap<IconState, String>,
+}
+
+impl UserIcons {
+  /// Loads the icon of every state from `directory`, skipping missing files
+  /// and files that are not valid SVG
+  pub fn load(directory: &Path) -> Self {
+    let options = Options::default();
+    let icons = IconState::ALL
+      .into_iter()
+      .filter_map(|state| {
+        let path = directory.join(format!("{}.svg", state.name()));
+        let svg = match fs::read_to_string(&path) {
+          Ok(svg) => svg,
+          Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            debug!("No icon for {state:?} at {}", path.display());
+            return None;
+          },
+          Err(e) => {
+            warn!("Failed to read icon {}: {e}", path.display());
+            return None;
+          },
+        };
+
+        if let Err(e) = Tree::from_str(&svg, &options) {
+          warn!("Ignoring invalid icon {}: {e}", path.display());
+          return None;
+        }
+        Some((state, svg))
+      })
+      .collect::<HashMap<_, _>>();
+
+    info!(
+      "Loaded {} of {} icons from {}",
+      icons.len(),
+      IconState::ALL.len(),
+      directory.display()
+    );
+    Self {
+      directory: Some(directory.to_owned()),
+      icons,
+    }
+  }
+
+  /// Returns the SVG replacing the built-in icon for `state`, if any
+  pub fn get(&self, state: IconState) -> Option<&str> {
+    self.icons.get(&state).map(String::as_str)
+  }
+}
+
+/// The directory being watched and the handle to change that
+struct Watcher {
+  watches: Watches,
+  watched: Option<(PathBuf, WatchDescriptor)>,
+}
+
+impl Watcher {
+  /// Moves the watch over to `directory`
+  fn retarget(&mut self, directory: Option<&Path>) {
+    if self.watched.as_ref().map(|(path, _)| path.as_path()) == directory {
+      return;
+    }
+
+    if let Some((path, descriptor)) = self.watched.take()
+      && let Err(e) = self.watches.remove(descriptor)
+    {
+      debug!("Failed to stop watching {}: {e}", path.display());
+    }
+
+    let Some(directory) = directory else {
+      return;
+    };
+    match self.watches.add(
+      directory,
+      WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM
+        | WatchMask::DELETE,
+    ) {
+      Ok(descriptor) => {
+        info!("Watching {} for icon changes", directory.display());
+        self.watched = Some((directory.to_owned(), descriptor));
+      },
+      Err(e) => warn!("Failed to watch {}: {e}", directory.display()),
+    }
+  }
+}
+
+/// Returns the icons currently in effect
+pub fn current() -> Arc<UserIcons> {
+  CURRENT
+    .read()
+    .unwrap_or_else(PoisonError::into_inner)
+    .clone()
+    .unwrap_or_default()
+}
+
+/// Re-reads the icon directory of the current configuration, returning
+/// whether any icon changed
+pub fn reload() -> bool {
+  let directory = config::current().icons.directory.clone();
+  let icons = directory
+    .as_deref()
+    .map(UserIcons::load)
+    .unwrap_or_default();
+
+  if let Some(watcher) = WATCHER
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+    .as_mut()
+  {
+    watcher.retarget(directory.as_deref());
+  }
+
+  let mut current = CURRENT.write().unwrap_or_else(PoisonError::into_inner);
+  if current.as_deref().unwrap_or(&UserIcons::default()) == &icons {
+    return false;
+  }
+  *current = Some(Arc::new(icons));
+  // Rendering reads the icons while holding the cache
+  drop(current);
+  Resvg::clear_cache();
+  true
+}
+
+/// Loads the icons and reloads them whenever a file in the icon directory
+/// changes, calling `on_reload` if that changed any icon
+///
+/// The watch follows the directory to wherever [`reload`] finds it in the
+/// configuration.
+pub fn watch<F>(mut on_reload: F) -> io::Result<()>
+where
+  F: FnMut() + Send + 'static,
+{
+  // Without a watch, the icons are still loaded once
+  let mut inotify = Inotify::init().inspect_err(|_| {
+    reload();
+  })?;
+  *WATCHER.lock().unwrap_or_else(PoisonError::into_inner) = Some(Watcher {
+    watches: inotify.watches(),
+    watched: None,
+  });
+  reload();
+
+  thread::spawn(move || {
+    let mut buffer = [0; 4096];
+    loop {
+      if let Err(e) = inotify.read_events_blocking(&mut buffer) {
+        error!("Stopped watching the icon directory: {e}");
+        return;
+      }
+
+      // Let programs that write in several steps finish, then drop the
+      // events they caused in the meantime
+      thread::sleep(RELOAD_DEBOUNCE);
+      while inotify
+        .read_events(&mut buffer)
+        .is_ok_and(|mut events| events.next().is_some())
+      {}
+
+      if reload() {
+        info!("Reloaded icons");
+        on_reload();
+      }
+    }
+  });
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_valid_icons_are_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+      dir.path().join("connected.svg"),
+      r#"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16"><circle cx="8" cy="8" r="8"/></svg>"#,
+    )
+    .unwrap();
+    fs::write(dir.path().join("disconnected.svg"), "not an svg").unwrap();
+
+    let icons = UserIcons::load(dir.path());
+
+    assert!(icons.get(IconState::Running).is_some());
+    assert!(icons.get(IconState::Stopped).is_none());
+    assert!(icons.get(IconState::ExitNode).is_none());
+  }
+}
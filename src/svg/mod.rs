@@ -1 +1,3 @@
+pub mod icons;
 pub mod renderer;
+pub mod symbolic;
@@ -1,50 +1,163 @@
-use std::{error::Error, fmt};
+use std::{
+  collections::HashMap,
+  error::Error,
+  fmt,
+  sync::{LazyLock, Mutex, PoisonError},
+};
 
 use ksni::Icon;
 use log::{debug, error};
 use resvg::{
   self,
-  tiny_skia::{Pixmap, Transform},
+  tiny_skia::{Pixmap, PixmapPaint, PremultipliedColorU8, Transform},
   usvg::{Options, Tree},
 };
+use serde::Deserialize;
+
+use crate::{
+  appearance::{self, ColorScheme},
+  svg::icons,
+  tailscale::status::Status,
+};
 
 const SVG_DATA_LIGHT: &str = include_str!("assets/tailscale-light.svg");
 const SVG_DATA_DARK: &str = include_str!("assets/tailscale-dark.svg");
 
-const DISABLED_OPACITY: &str = "0.4";
-const ENABLED_OPACITY: &str = "1.0";
+const BADGE_EXIT_NODE: &str = include_str!("assets/badges/exit-node.svg");
+const BADGE_HEALTH_WARNING: &str =
+  include_str!("assets/badges/health-warning.svg");
+const BADGE_NEEDS_LOGIN: &str = include_str!("assets/badges/needs-login.svg");
+const BADGE_NEEDS_MACHINE_AUTH: &str =
+  include_str!("assets/badges/needs-machine-auth.svg");
+const BADGE_STARTING: &str = include_str!("assets/badges/starting.svg");
+const BADGE_NO_STATE: &str = include_str!("assets/badges/no-state.svg");
+
+/// Opacity of the base icon while not connected
+const INACTIVE_OPACITY: f32 = 0.4;
+
+/// Share of the icon's width covered by a badge
+const BADGE_SCALE: f32 = 0.6;
+
+/// Sizes rendered for hosts to pick from, in pixels
+const ICON_SIZES: [u32; 7] = [16, 22, 24, 32, 48, 64, 128];
+
+/// Resolved theme, state and size of a rendered icon
+type CacheKey = (Theme, IconState, u32);
+
+/// Icons rendered so far
+static CACHE: LazyLock<Mutex<HashMap<CacheKey, Icon>>> =
+  LazyLock::new(Mutex::default);
 
 /// Icon theme variant
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
 pub enum Theme {
   #[default]
   Light,
   Dark,
+  /// Follow the desktop's light/dark preference
+  Auto,
 }
 
 impl Theme {
-  /// Parse theme from environment variable TAILRAY_THEME
-  pub fn from_env() -> Self {
-    std::env::var("TAILRAY_THEME")
-      .map(|s| {
-        let s = s.to_lowercase();
-        match s.as_str() {
-          "dark" => Theme::Dark,
-          "light" => Theme::Light,
-          _ => {
-            log::warn!("Invalid theme value '{s}', defaulting to light");
-            Theme::Light
-          },
+  /// Resolves [`Theme::Auto`] to the theme matching the desktop's
+  /// preference, or to the light theme when there is none
+  pub fn resolve(self) -> Self {
+    match self {
+      Self::Auto => {
+        match appearance::color_scheme() {
+          ColorScheme::Dark => Self::Dark,
+          ColorScheme::Light | ColorScheme::NoPreference => Self::Light,
         }
-      })
-      .unwrap_or_default()
+      },
+      theme => theme,
+    }
   }
 
   /// Get the SVG data for this theme
-  const fn svg_data(&self) -> &'static str {
-    match self {
-      Self::Light => SVG_DATA_LIGHT,
+  fn svg_data(self) -> &'static str {
+    match self.resolve() {
       Self::Dark => SVG_DATA_DARK,
+      Self::Light | Self::Auto => SVG_DATA_LIGHT,
+    }
+  }
+}
+
+/// What the tray icon shows, derived from the backend state
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconState {
+  #[serde(rename = "connected")]
+  Running,
+  #[serde(rename = "disconnected")]
+  Stopped,
+  NeedsLogin,
+  NeedsMachineAuth,
+  Starting,
+  NoState,
+  /// Running with traffic routed through an exit node
+  ExitNode,
+  /// Running while tailscaled reports health warnings
+  HealthWarning,
+}
+
+impl IconState {
+  /// Every state, in no particular order
+  pub const ALL: [Self; 8] = [
+    Self::Running,
+    Self::Stopped,
+    Self::NeedsLogin,
+    Self::NeedsMachineAuth,
+    Self::Starting,
+    Self::NoState,
+    Self::ExitNode,
+    Self::HealthWarning,
+  ];
+
+  /// Picks the state to show for `status`, preferring health warnings over
+  /// the exit node badge while running
+  pub fn new(status: &Status) -> Self {
+    match status.backend_state() {
+      "Running" if !status.health().is_empty() => Self::HealthWarning,
+      "Running" if status.exit_node().is_some() => Self::ExitNode,
+      "Running" => Self::Running,
+      "Stopped" => Self::Stopped,
+      "NeedsLogin" => Self::NeedsLogin,
+      "NeedsMachineAuth" => Self::NeedsMachineAuth,
+      "Starting" => Self::Starting,
+      _ => Self::NoState,
+    }
+  }
+
+  /// Returns the name of the state in icon file names and configuration
+  pub const fn name(self) -> &'static str {
+    match self {
+      Self::Running => "connected",
+      Self::Stopped => "disconnected",
+      Self::NeedsLogin => "needs-login",
+      Self::NeedsMachineAuth => "needs-machine-auth",
+      Self::Starting => "starting",
+      Self::NoState => "no-state",
+      Self::ExitNode => "exit-node",
+      Self::HealthWarning => "health-warning",
+    }
+  }
+
+  /// Returns whether the base icon is dimmed, as it is while not connected
+  const fn inactive(self) -> bool {
+    !matches!(self, Self::Running | Self::ExitNode | Self::HealthWarning)
+  }
+
+  /// Returns the badge drawn over the base icon, if any
+  const fn badge(self) -> Option<&'static str> {
+    match self {
+      Self::Running | Self::Stopped => None,
+      Self::NeedsLogin => Some(BADGE_NEEDS_LOGIN),
+      Self::NeedsMachineAuth => Some(BADGE_NEEDS_MACHINE_AUTH),
+      Self::Starting => Some(BADGE_STARTING),
+      Self::NoState => Some(BADGE_NO_STATE),
+      Self::ExitNode => Some(BADGE_EXIT_NODE),
+      Self::HealthWarning => Some(BADGE_HEALTH_WARNING),
     }
   }
 }
@@ -74,67 +187,225 @@ pub struct Resvg<'a> {
 }
 
 impl Resvg<'_> {
-  /// Convert an SVG string to a KDE Systray Icon
-  #[allow(clippy::cast_sign_loss)]
-  #[allow(clippy::cast_possible_truncation)]
-  pub fn to_icon(&self, svg_str: &str) -> Result<Icon, RenderError> {
-    // Parse the SVG string
-    let tree = Tree::from_str(svg_str, &self.options)
-      .map_err(|e| RenderError::TreeParsing(e.to_string()))?;
-
-    // Get the size from the SVG
-    let size = tree.size();
-    let width = size.width() as u32;
-    let height = size.height() as u32;
-
-    // Create a pixmap to render into
-    let mut pixmap = Pixmap::new(width, height).ok_or_else(|| {
-      RenderError::PixmapCreation("Failed to create pixmap".into())
-    })?;
-
-    // Render the SVG to the pixmap
-    resvg::render(&tree, self.transform, &mut pixmap.as_mut());
-
-    // Convert from RGBA to ARGB format for KDE system tray
-    let argb_data: Vec<u8> = pixmap
-      .take()
-      .chunks_exact(4)
-      .flat_map(|rgba| [rgba[3], rgba[0], rgba[1], rgba[2]])
-      .collect();
+  /// Render the icon for `state` as a KDE Systray Icon of `size` by `size`
+  /// pixels
+  ///
+  /// A user icon for `state` is scaled to fit. Otherwise the base icon is
+  /// painted first, dimmed while not connected, and the state's badge is
+  /// then composed over its bottom right corner.
+  #[allow(clippy::cast_precision_loss)]
+  #[allow(clippy::cast_possible_wrap)]
+  pub fn render(
+    &self,
+    theme: Theme,
+    state: IconState,
+    size: u32,
+  ) -> Result<Icon, RenderError> {
+    let side = size as f32;
+    let mut pixmap = new_pixmap(size, size)?;
+
+    if let Some(svg) = icons::current().get(state) {
+      let tree = self.parse(svg)?;
+      resvg::render(&tree, self.fit(&tree, side, 0.0), &mut pixmap.as_mut());
+    } else {
+      let base = self.parse(theme.svg_data())?;
+      let transform = self.fit(&base, side, 0.0);
+      if state.inactive() {
+        let mut layer = new_pixmap(size, size)?;
+        resvg::render(&base, transform, &mut layer.as_mut());
+        let paint = PixmapPaint {
+          opacity: INACTIVE_OPACITY,
+          ..PixmapPaint::default()
+        };
+        pixmap.draw_pixmap(
+          0,
+          0,
+          layer.as_ref(),
+          &paint,
+          Transform::identity(),
+          None,
+        );
+      } else {
+        resvg::render(&base, transform, &mut pixmap.as_mut());
+      }
+
+      if let Some(badge) = state.badge() {
+        let badge = self.parse(badge)?;
+        let badge_side = side * BADGE_SCALE;
+        let transform = self.fit(&badge, badge_side, side - badge_side);
+        resvg::render(&badge, transform, &mut pixmap.as_mut());
+      }
+    }
 
-    // Create the Icon
     Ok(Icon {
-      width:  size.width() as i32,
-      height: size.height() as i32,
-      data:   argb_data,
+      width:  size as i32,
+      height: size as i32,
+      data:   to_argb(pixmap.pixels()),
     })
   }
 
-  /// Load appropriate icon based on connection state and theme
-  pub fn load_icon(theme: Theme, enabled: bool) -> Vec<Icon> {
+  /// Load the icon for `state` in `theme` at every size in [`ICON_SIZES`],
+  /// rendering only those not cached yet
+  pub fn load_icon(theme: Theme, state: IconState) -> Vec<Icon> {
+    let theme = theme.resolve();
     let renderer = Self::default();
-    let svg_data = theme.svg_data();
-
-    if enabled {
-      debug!("Loading enabled Tailscale icon (theme: {theme:?})");
-      match renderer.to_icon(svg_data) {
-        Ok(icon) => vec![icon],
-        Err(e) => {
-          error!("Failed to load enabled icon: {e}");
-          Vec::new()
-        },
+    let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+
+    ICON_SIZES
+      .into_iter()
+      .filter_map(|size| {
+        let key = (theme, state, size);
+        if let Some(icon) = cache.get(&key) {
+          return Some(icon.clone());
+        }
+
+        debug!("Rendering {size}px icon for {state:?} (theme: {theme:?})");
+        match renderer.render(theme, state, size) {
+          Ok(icon) => {
+            cache.insert(key, icon.clone());
+            Some(icon)
+          },
+          Err(e) => {
+            error!("Failed to render {size}px icon for {state:?}: {e}");
+            None
+          },
+        }
+      })
+      .collect()
+  }
+
+  /// Drops every cached icon, so that the next load renders them again
+  pub fn clear_cache() {
+    CACHE.lock().unwrap_or_else(PoisonError::into_inner).clear();
+  }
+
+  fn parse(&self, svg_str: &str) -> Result<Tree, RenderError> {
+    Tree::from_str(svg_str, &self.options)
+      .map_err(|e| RenderError::TreeParsing(e.to_string()))
+  }
+
+  /// Returns the transform scaling `tree` to fit, centered, into the square
+  /// of `side` pixels whose top left corner is at (`offset`, `offset`)
+  fn fit(&self, tree: &Tree, side: f32, offset: f32) -> Transform {
+    let size = tree.size();
+    let scale = (side / size.width()).min(side / size.height());
+    self
+      .transform
+      .pre_translate(
+        offset + (side - size.width() * scale) / 2.0,
+        offset + (side - size.height() * scale) / 2.0,
+      )
+      .pre_scale(scale, scale)
+  }
+}
+
+/// Converts premultiplied RGBA pixels to the straight ARGB the KDE system
+/// tray expects
+fn to_argb(pixels: &[PremultipliedColorU8]) -> Vec<u8> {
+  pixels
+    .iter()
+    .flat_map(|pixel| {
+      let color = pixel.demultiply();
+      [color.alpha(), color.red(), color.green(), color.blue()]
+    })
+    .collect()
+}
+
+fn new_pixmap(width: u32, height: u32) -> Result<Pixmap, RenderError> {
+  Pixmap::new(width, height).ok_or_else(|| {
+    RenderError::PixmapCreation("Failed to create pixmap".into())
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(theme: Theme, state: IconState) -> Icon {
+    Resvg::default()
+      .render(theme, state, 32)
+      .unwrap_or_else(|e| panic!("{state:?} should render: {e}"))
+  }
+
+  #[test]
+  fn every_state_renders_a_visible_icon_at_every_size() {
+    for theme in [Theme::Light, Theme::Dark] {
+      for state in IconState::ALL {
+        let icons = Resvg::load_icon(theme, state);
+        let sizes: Vec<_> = icons.iter().map(|icon| icon.width).collect();
+        assert_eq!(sizes, [16, 22, 24, 32, 48, 64, 128], "{state:?}");
+
+        for icon in icons {
+          assert_eq!(icon.width, icon.height);
+          assert_eq!(icon.data.len(), (icon.width * icon.height * 4) as usize);
+          assert!(
+            icon.data.chunks_exact(4).any(|argb| argb[0] > 0),
+            "{state:?} in {theme:?} is fully transparent at {}px",
+            icon.width
+          );
+        }
       }
-    } else {
-      debug!("Loading disabled Tailscale icon (theme: {theme:?})");
-      // Replace opacity in SVG
-      let disabled_svg = svg_data.replace(ENABLED_OPACITY, DISABLED_OPACITY);
-      match renderer.to_icon(&disabled_svg) {
-        Ok(icon) => vec![icon],
-        Err(e) => {
-          error!("Failed to load disabled icon: {e}");
-          Vec::new()
-        },
+    }
+  }
+
+  #[test]
+  fn cached_icons_match_fresh_renders() {
+    let state = IconState::ExitNode;
+    let cached = Resvg::load_icon(Theme::Dark, state);
+    let again = Resvg::load_icon(Theme::Dark, state);
+    let fresh = Resvg::default().render(Theme::Dark, state, 48).unwrap();
+
+    assert_eq!(
+      cached.iter().map(|icon| &icon.data).collect::<Vec<_>>(),
+      again.iter().map(|icon| &icon.data).collect::<Vec<_>>()
+    );
+    assert_eq!(cached[4].data, fresh.data);
+  }
+
+  #[test]
+  fn pixels_are_converted_to_straight_argb() {
+    let mut pixmap = Pixmap::new(3, 1).unwrap();
+    pixmap.pixels_mut()[0] =
+      PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+    // Half-transparent blue, premultiplied
+    pixmap.pixels_mut()[1] =
+      PremultipliedColorU8::from_rgba(0, 0, 128, 128).unwrap();
+
+    assert_eq!(to_argb(pixmap.pixels()), [
+      255, 255, 0, 0, // opaque red
+      128, 0, 0, 255, // half-transparent blue
+      0, 0, 0, 0, // transparent
+    ]);
+  }
+
+  #[test]
+  fn every_state_looks_different() {
+    let icons: Vec<_> = IconState::ALL
+      .iter()
+      .map(|&state| render(Theme::Light, state).data)
+      .collect();
+
+    for (i, a) in icons.iter().enumerate() {
+      for (j, b) in icons.iter().enumerate().skip(i + 1) {
+        assert_ne!(
+          a,
+          b,
+          "{:?} and {:?} render alike",
+          IconState::ALL[i],
+          IconState::ALL[j]
+        );
       }
     }
   }
+
+  #[test]
+  fn state_follows_status() {
+    let status = crate::tailscale::status::parse(include_str!(
+      "../tailscale/testdata/status.json"
+    ))
+    .expect("fixture should parse");
+
+    // The fixture uses an exit node and reports a health warning
+    assert_eq!(IconState::new(&status), IconState::HealthWarning);
+  }
 }
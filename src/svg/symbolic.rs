@@ -0,0 +1,113 @@
+use std::{
+  fs,
+  io,
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
+
+use log::{info, warn};
+
+use crate::{config, svg::renderer::IconState};
+
+/// Embeds the bundled symbolic icon named after a state
+macro_rules! bundled {
+  ($state:literal) => {
+    include_str!(concat!(
+      "../../data/icons/hicolor/scalable/status/tailray-",
+      $state,
+      "-symbolic.svg"
+    ))
+  };
+}
+
+/// The bundled symbolic icons, as installed to
+/// `share/icons/hicolor/scalable/status`
+const ICONS: [(IconState, &str); 8] = [
+  (IconState::Running, bundled!("connected")),
+  (IconState::Stopped, bundled!("disconnected")),
+  (IconState::NeedsLogin, bundled!("needs-login")),
+  (IconState::NeedsMachineAuth, bundled!("needs-machine-auth")),
+  (IconState::Starting, bundled!("starting")),
+  (IconState::NoState, bundled!("no-state")),
+  (IconState::ExitNode, bundled!("exit-node")),
+  (IconState::HealthWarning, bundled!("health-warning")),
+];
+
+/// Makes the theme directory an icon theme Qt-based hosts can search
+const INDEX_THEME: &str = "[Icon Theme]
+Name=Hicolor
+Directories=scalable/status
+
+[scalable/status]
+Size=16
+MinSize=8
+MaxSize=512
+Type=Scalable
+Context=Status
+";
+
+/// The theme path holding the bundled icons, once installed
+static THEME_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Returns the name of the bundled symbolic icon for `state`
+pub fn icon_name(state: IconState) -> String {
+  format!("tailray-{}-symbolic", state.name())
+}
+
+/// Returns a theme path holding the bundled icons, copying them to
+/// `$XDG_DATA_HOME/tailray/icons` on first use so that hosts find them even
+/// when Tailray is not installed system-wide
+pub fn theme_path() -> Option<&'static Path> {
+  THEME_PATH
+    .get_or_init(|| {
+      install()
+        .inspect_err(|e| warn!("Failed to install the bundled icons: {e}"))
+        .ok()
+    })
+    .as_deref()
+}
+
+/// Writes the bundled icons that are missing or outdated
+fn install() -> io::Result<PathBuf> {
+  let root = config::data_home()
+    .ok_or_else(|| io::Error::other("no data directory could be determined"))?
+    .join("tailray")
+    .join("icons");
+  let theme = root.join("hicolor");
+  let status = theme.join("scalable").join("status");
+  fs::create_dir_all(&status)?;
+
+  let files = ICONS
+    .into_iter()
+    .map(|(state, svg)| (status.join(format!("{}.svg", icon_name(state))), svg))
+    .chain([(theme.join("index.theme"), INDEX_THEME)]);
+  for (path, contents) in files {
+    if fs::read_to_string(&path).ok().as_deref() != Some(contents) {
+      fs::write(&path, contents)?;
+    }
+  }
+
+  info!("Installed the bundled icons to {}", root.display());
+  Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+  use resvg::usvg::{Options, Tree};
+
+  use super::*;
+
+  #[test]
+  fn bundled_icons_cover_every_state_and_parse() {
+    for state in IconState::ALL {
+      let (_, svg) = ICONS
+        .iter()
+        .find(|(icon_state, _)| *icon_state == state)
+        .unwrap_or_else(|| panic!("no bundled icon for {state:?}"));
+      assert!(
+        Tree::from_str(svg, &Options::default()).is_ok(),
+        "{state:?} does not parse"
+      );
+    }
+  }
+}
@@ -0,0 +1,94 @@
+use serde::Deserialize;
+
+use crate::tailscale::{status::Status, utils::Machine};
+
+/// The exit node tailscaled recommends for this device
+#[derive(Deserialize, Debug, Default)]
+pub struct ExitNodeSuggestion {
+  #[serde(rename = "ID", default)]
+  pub id: String,
+
+  #[serde(rename = "Name", default)]
+  pub name: String,
+}
+
+impl ExitNodeSuggestion {
+  /// Returns the `--exit-node` argument selecting this suggestion, preferring
+  /// the peer's Tailscale IP over its name
+  pub fn target(&self, status: &Status) -> String {
+    status
+      .peers
+      .values()
+      .find(|peer| peer.id == self.id)
+      .and_then(|peer| peer.ips.first().map(ToString::to_string))
+      .unwrap_or_else(|| self.name.trim_end_matches('.').to_owned())
+  }
+}
+
+/// Returns all peers offering to act as an exit node, sorted by name
+pub fn options(status: &Status) -> Vec<&Machine> {
+  let mut options: Vec<_> = status
+    .peers
+    .values()
+    .filter(|peer| peer.exit_node_option && !peer.ips.is_empty())
+    .collect();
+  options.sort_by_cached_key(|peer| peer.display_name.to_string());
+  options
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("testdata/status.json");
+
+  fn suggestion(id: &str, name: &str) -> ExitNodeSuggestion {
+    ExitNodeSuggestion {
+      id:   id.to_owned(),
+      name: name.to_owned(),
+    }
+  }
+
+  #[test]
+  fn options_are_exit_nodes_sorted_by_name() {
+    let mut status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let names = |status: &Status| {
+      options(status)
+        .iter()
+        .map(|peer| peer.hostname.clone())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&status), ["nas"]);
+
+    for peer in status.peers.values_mut() {
+      peer.exit_node_option = peer.hostname != "printer-gw";
+    }
+    assert_eq!(names(&status), [
+      "bobs-macbook",
+      "build-runner-01",
+      "carols-phone",
+      "nas"
+    ]);
+
+    for peer in status.peers.values_mut() {
+      if peer.hostname != "nas" {
+        peer.ips.clear();
+      }
+    }
+    assert_eq!(names(&status), ["nas"], "peers without IPs are skipped");
+  }
+
+  #[test]
+  fn suggestion_targets_prefer_known_peers_ip() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+
+    let known = suggestion("nXq1Y3h8e811CNTRL", "nas.tail2f4d1.ts.net.");
+    assert_eq!(known.target(&status), "100.88.12.4");
+
+    let unknown = suggestion("nUnknown", "de-fra.mullvad.ts.net.");
+    assert_eq!(unknown.target(&status), "de-fra.mullvad.ts.net");
+
+    assert_eq!(ExitNodeSuggestion::default().target(&status), "");
+  }
+}
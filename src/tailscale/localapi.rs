@@ -0,0 +1,462 @@
+use std::{
+  error::Error,
+  fmt,
+  io::{BufRead, BufReader, Read, Write},
+  os::unix::net::UnixStream,
+  path::PathBuf,
+  time::Duration,
+};
+
+use log::{debug, error};
+
+use crate::{
+  elevation::detection::tailscale_socket_path,
+  tailscale::{
+    exit_node::ExitNodeSuggestion,
+    prefs::Prefs,
+    status::{self, Status},
+    watch::IpnBus,
+  },
+};
+
+/// Host name the official clients send to the LocalAPI
+const LOCALAPI_HOST: &str = "local-tailscaled.sock";
+
+/// Prefix shared by all LocalAPI endpoints
+const LOCALAPI_PREFIX: &str = "/localapi/v0/";
+
+/// How long to wait for tailscaled before giving up on a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the control server to hand out a login URL
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors that can occur while talking to the LocalAPI
+#[derive(Debug)]
+pub enum LocalApiError {
+  /// The tailscaled socket could not be reached
+  Connect(String),
+  /// Reading from or writing to the socket failed
+  Io(String),
+  /// tailscaled answered with a non-success HTTP status
+  Http { status: u16, body: String },
+  /// The HTTP response could not be understood
+  InvalidResponse(String),
+  /// The response body did not match the expected schema
+  Parse(String),
+}
+
+impl fmt::Display for LocalApiError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Connect(msg) => write!(f, "Cannot connect to tailscaled: {msg}"),
+      Self::Io(msg) => write!(f, "LocalAPI I/O error: {msg}"),
+      Self::Http { status, body } => {
+        write!(f, "LocalAPI returned HTTP {status}: {}", body.trim())
+      },
+      Self::InvalidResponse(msg) => {
+        write!(f, "Invalid LocalAPI response: {msg}")
+      },
+      Self::Parse(msg) => write!(f, "Failed to parse LocalAPI response: {msg}"),
+    }
+  }
+}
+
+impl Error for LocalApiError {}
+
+impl From<std::io::Error> for LocalApiError {
+  fn from(e: std::io::Error) -> Self {
+    Self::Io(e.to_string())
+  }
+}
+
+/// Client for tailscaled's LocalAPI, spoken as HTTP/1.0 over the daemon's
+/// Unix socket
+#[derive(Debug, Clone)]
+pub struct LocalApi {
+  socket_path: PathBuf,
+  /// How long to wait for tailscaled to accept or answer a request
+  timeout:     Duration,
+}
+
+impl Default for LocalApi {
+  fn default() -> Self {
+    Self::new(tailscale_socket_path())
+  }
+}
+
+impl LocalApi {
+  /// Creates a client talking to the socket at `socket_path`
+  pub fn new(socket_path: impl Into<PathBuf>) -> Self {
+    Self {
+      socket_path: socket_path.into(),
+      timeout:     REQUEST_TIMEOUT,
+    }
+  }
+
+  /// Fetches the full status of the tailnet, including peers
+  pub fn status(&self) -> Result<Status, LocalApiError> {
+    let body = self.get("status")?;
+    status::parse(&body).map_err(|e| LocalApiError::Parse(e.to_string()))
+  }
+
+  /// Fetches the current preferences of this node
+  pub fn prefs(&self) -> Result<Prefs, LocalApiError> {
+    let body = self.get("prefs")?;
+    serde_json::from_str(&body).map_err(|e| LocalApiError::Parse(e.to_string()))
+  }
+
+  /// Asks tailscaled which exit node it would recommend for this device
+  pub fn suggest_exit_node(&self) -> Result<ExitNodeSuggestion, LocalApiError> {
+    let body = self.get("suggest-exit-node")?;
+    serde_json::from_str(&body).map_err(|e| LocalApiError::Parse(e.to_string()))
+  }
+
+  /// Starts an interactive login, as `tailscale login` does, and returns the
+  /// URL at which the user completes it
+  pub fn login_interactive(&self) -> Result<String, LocalApiError> {
+    // Subscribe first so that the URL cannot be missed
+    let mut bus = IpnBus::watch(self, 0)?;
+    bus.set_timeout(LOGIN_TIMEOUT)?;
+    self.request("POST", "login-interactive", None)?;
+
+    bus
+      .find_map(|notify| {
+        match notify {
+          Ok(notify) => notify.browse_to_url.map(Ok),
+          Err(e) => Some(Err(e)),
+        }
+      })
+      .unwrap_or_else(|| {
+        Err(LocalApiError::InvalidResponse(
+          "notification bus closed before a login URL was sent".into(),
+        ))
+      })
+  }
+
+  /// Performs a GET request against `endpoint` and returns the response body
+  pub fn get(&self, endpoint: &str) -> Result<String, LocalApiError> {
+    self.request("GET", endpoint, None)
+  }
+
+  /// Performs a request against `endpoint` and returns the response body
+  ///
+  /// # Arguments
+  /// * `method` - The HTTP method to use
+  /// * `endpoint` - Path below `/localapi/v0/`, including any query string
+  /// * `body` - Optional JSON request body
+  pub fn request(
+    &self,
+    method: &str,
+    endpoint: &str,
+    body: Option<&str>,
+  ) -> Result<String, LocalApiError> {
+    let mut response = self.send(method, endpoint, body)?;
+    let body = response.read_body()?;
+    if !(200..300).contains(&response.status) {
+      error!(
+        "LocalAPI {method} {endpoint} failed: HTTP {}",
+        response.status
+      );
+      return Err(LocalApiError::Http {
+        status: response.status,
+        body,
+      });
+    }
+
+    Ok(body)
+  }
+
+  /// Opens a long-lived GET request against `endpoint` and returns a reader
+  /// over the response body as tailscaled streams it
+  pub fn stream(
+    &self,
+    endpoint: &str,
+  ) -> Result<BufReader<UnixStream>, LocalApiError> {
+    let mut response = self.send("GET", endpoint, None)?;
+    if !(200..300).contains(&response.status) {
+      let body = response.read_body()?;
+      error!("LocalAPI GET {endpoint} failed: HTTP {}", response.status);
+      return Err(LocalApiError::Http {
+        status: response.status,
+        body,
+      });
+    }
+
+    // The body is read for as long as tailscaled keeps streaming it
+    response.stream.get_ref().set_read_timeout(None)?;
+    Ok(response.stream)
+  }
+
+  /// Sends a request and reads the response head, leaving the body unread
+  fn send(
+    &self,
+    method: &str,
+    endpoint: &str,
+    body: Option<&str>,
+  ) -> Result<Response, LocalApiError> {
+    let mut stream = UnixStream::connect(&self.socket_path).map_err(|e| {
+      debug!("Failed to connect to {}: {e}", self.socket_path.display());
+      LocalApiError::Connect(format!("{}: {e}", self.socket_path.display()))
+    })?;
+    stream.set_write_timeout(Some(self.timeout))?;
+    stream.set_read_timeout(Some(self.timeout))?;
+
+    let body = body.unwrap_or_default();
+    let request = format!(
+      "{method} {LOCALAPI_PREFIX}{endpoint} HTTP/1.0\r\nHost: \
+       {LOCALAPI_HOST}\r\nContent-Type: application/json\r\nContent-Length: \
+       {}\r\n\r\n{body}",
+      body.len()
+    );
+    debug!("LocalAPI request: {method} {LOCALAPI_PREFIX}{endpoint}");
+    stream.write_all(request.as_bytes())?;
+
+    Response::read_head(BufReader::new(stream))
+  }
+}
+
+/// An HTTP response whose body has not been consumed yet
+struct Response {
+  status:  u16,
+  headers: Vec<(String, String)>,
+  stream:  BufReader<UnixStream>,
+}
+
+impl Response {
+  /// Parses the status line and headers from `stream`
+  fn read_head(
+    mut stream: BufReader<UnixStream>,
+  ) -> Result<Self, LocalApiError> {
+    let mut line = String::new();
+    stream.read_line(&mut line)?;
+
+    let status = line
+      .split_whitespace()
+      .nth(1)
+      .and_then(|code| code.parse::<u16>().ok())
+      .ok_or_else(|| {
+        LocalApiError::InvalidResponse(format!(
+          "malformed status line: {:?}",
+          line.trim()
+        ))
+      })?;
+
+    let mut headers = Vec::new();
+    loop {
+      line.clear();
+      if stream.read_line(&mut line)? == 0 {
+        return Err(LocalApiError::InvalidResponse(
+          "connection closed before end of headers".into(),
+        ));
+      }
+
+      let header = line.trim_end();
+      if header.is_empty() {
+        break;
+      }
+
+      if let Some((name, value)) = header.split_once(':') {
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().into()));
+      }
+    }
+
+    Ok(Self {
+      status,
+      headers,
+      stream,
+    })
+  }
+
+  /// Returns the value of the header `name`, if present
+  fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(key, _)| key.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+
+  /// Reads the complete response body
+  fn read_body(&mut self) -> Result<String, LocalApiError> {
+    let chunked = self
+      .header("transfer-encoding")
+      .is_some_and(|te| te.eq_ignore_ascii_case("chunked"));
+
+    let mut body = Vec::new();
+    if chunked {
+      self.read_chunked(&mut body)?;
+    } else if let Some(length) = self.header("content-length") {
+      let length = length.parse::<u64>().map_err(|e| {
+        LocalApiError::InvalidResponse(format!("bad Content-Length: {e}"))
+      })?;
+      (&mut self.stream).take(length).read_to_end(&mut body)?;
+    } else {
+      self.stream.read_to_end(&mut body)?;
+    }
+
+    String::from_utf8(body).map_err(|e| {
+      LocalApiError::InvalidResponse(format!("invalid UTF-8 in body: {e}"))
+    })
+  }
+
+  /// Decodes a `Transfer-Encoding: chunked` body into `body`
+  fn read_chunked(&mut self, body: &mut Vec<u8>) -> Result<(), LocalApiError> {
+    let mut line = String::new();
+    loop {
+      line.clear();
+      self.stream.read_line(&mut line)?;
+
+      let size = line.trim().split(';').next().unwrap_or_default();
+      let size = usize::from_str_radix(size, 16).map_err(|e| {
+        LocalApiError::InvalidResponse(format!("bad chunk size {size:?}: {e}"))
+      })?;
+      if size == 0 {
+        return Ok(());
+      }
+
+      let start = body.len();
+      body.resize(start + size, 0);
+      self.stream.read_exact(&mut body[start..])?;
+
+      // Every chunk is followed by a CRLF
+      line.clear();
+      self.stream.read_line(&mut line)?;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{os::unix::net::UnixListener, thread};
+
+  use super::*;
+
+  const STATUS_JSON: &str = r#"{
+    "BackendState": "Running",
+    "MagicDNSSuffix": "example.ts.net",
+    "Self": {
+      "DNSName": "laptop.example.ts.net.",
+      "HostName": "laptop",
+      "TailscaleIPs": ["100.64.0.1", "fd7a:115c:a1e0::1"]
+    },
+    "Peer": {
+      "nodekey:abc": {
+        "DNSName": "server.example.ts.net.",
+        "HostName": "server",
+        "TailscaleIPs": ["100.64.0.2"]
+      }
+    }
+  }"#;
+
+  /// Serves a single canned `response` on a fresh socket and returns the
+  /// client together with a handle yielding the request that was received
+  fn serve_once(
+    response: String,
+  ) -> (LocalApi, tempfile::TempDir, thread::JoinHandle<String>) {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let path = dir.path().join("tailscaled.sock");
+    let listener = UnixListener::bind(&path).expect("failed to bind socket");
+
+    let server = thread::spawn(move || {
+      let (stream, _) = listener.accept().expect("failed to accept");
+      let mut reader = BufReader::new(stream);
+      let mut request = String::new();
+      loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("failed to read request");
+        if line == "\r\n" || line.is_empty() {
+          break;
+        }
+        request.push_str(&line);
+      }
+      reader
+        .get_mut()
+        .write_all(response.as_bytes())
+        .expect("failed to write response");
+      request
+    });
+
+    (LocalApi::new(path), dir, server)
+  }
+
+  #[test]
+  fn status_is_parsed_from_content_length_body() {
+    let response = format!(
+      "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: \
+       {}\r\n\r\n{STATUS_JSON}",
+      STATUS_JSON.len()
+    );
+    let (api, _dir, server) = serve_once(response);
+
+    let status = api.status().expect("status request failed");
+    let request = server.join().unwrap();
+
+    assert!(request.starts_with("GET /localapi/v0/status HTTP/1.0\r\n"));
+    assert!(request.contains("Host: local-tailscaled.sock\r\n"));
+    assert!(status.tailscale_up);
+    assert_eq!(status.this_machine.ips[0].to_string(), "100.64.0.1");
+    assert_eq!(status.peers.len(), 1);
+  }
+
+  #[test]
+  fn chunked_body_is_decoded() {
+    let (head, tail) = STATUS_JSON.split_at(40);
+    let response = format!(
+      "HTTP/1.1 200 OK\r\nTransfer-Encoding: \
+       chunked\r\n\r\n{:x}\r\n{head}\r\n{:x}\r\n{tail}\r\n0\r\n\r\n",
+      head.len(),
+      tail.len()
+    );
+    let (api, _dir, server) = serve_once(response);
+
+    let body = api.get("status").expect("status request failed");
+    server.join().unwrap();
+
+    assert_eq!(body, STATUS_JSON);
+  }
+
+  #[test]
+  fn http_errors_carry_status_and_body() {
+    let response = "HTTP/1.0 403 Forbidden\r\n\r\naccess denied\n".to_string();
+    let (api, _dir, server) = serve_once(response);
+
+    let err = api.get("prefs").expect_err("request should fail");
+    let request = server.join().unwrap();
+
+    assert!(request.starts_with("GET /localapi/v0/prefs HTTP/1.0\r\n"));
+    match err {
+      LocalApiError::Http { status, body } => {
+        assert_eq!(status, 403);
+        assert_eq!(body, "access denied\n");
+      },
+      other => panic!("unexpected error: {other}"),
+    }
+  }
+
+  #[test]
+  fn silent_server_times_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tailscaled.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    // Accept the connection, then never answer
+    let server = thread::spawn(move || listener.accept().unwrap());
+    let api = LocalApi {
+      socket_path: path,
+      timeout:     Duration::from_millis(100),
+    };
+
+    let err = api.get("status").expect_err("request should time out");
+    drop(server.join().unwrap());
+
+    assert!(matches!(err, LocalApiError::Io(_)), "{err}");
+  }
+
+  #[test]
+  fn missing_socket_is_a_connect_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = LocalApi::new(dir.path().join("missing.sock"));
+
+    assert!(matches!(api.get("status"), Err(LocalApiError::Connect(_))));
+  }
+}
@@ -1,3 +1,7 @@
+pub mod exit_node;
+pub mod localapi;
 pub mod peer;
+pub mod prefs;
 pub mod status;
 pub mod utils;
+pub mod watch;
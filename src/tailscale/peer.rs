@@ -1,9 +1,28 @@
-use std::{error::Error, fmt};
+use std::{error::Error, fmt, path::PathBuf, process::Command};
 
 use log::{error, info};
-use notify_rust::Notification;
 
-use crate::{clipboard::copy_and_get, error::AppError};
+use crate::{
+  clipboard::copy_and_get,
+  elevation::run_with_elevation,
+  error::AppError,
+  notification::{self, Category},
+  tailscale::utils::Machine,
+};
+
+/// File choosers tried, in order, when sending a file via Taildrop
+const FILE_CHOOSERS: &[(&str, &[&str])] = &[
+  ("zenity", &[
+    "--file-selection",
+    "--title=Send file via Taildrop",
+  ]),
+  ("kdialog", &[
+    "--getopenfilename",
+    ".",
+    "--title",
+    "Send file via Taildrop",
+  ]),
+];
 
 /// Custom error type for peer operations
 #[derive(Debug)]
@@ -16,6 +35,10 @@ pub enum PeerError {
   NotificationError(String),
   /// Error when verification fails
   VerificationError(String),
+  /// Error when a tailscale command for the peer fails
+  CommandFailed(String),
+  /// Error when no file could be chosen for Taildrop
+  NoFileChosen(String),
 }
 
 impl fmt::Display for PeerError {
@@ -25,6 +48,8 @@ impl fmt::Display for PeerError {
       Self::ClipboardError(msg) => write!(f, "Clipboard error: {msg}"),
       Self::NotificationError(msg) => write!(f, "Notification error: {msg}"),
       Self::VerificationError(msg) => write!(f, "Verification error: {msg}"),
+      Self::CommandFailed(msg) => write!(f, "Peer command failed: {msg}"),
+      Self::NoFileChosen(msg) => write!(f, "No file chosen: {msg}"),
     }
   }
 }
@@ -63,34 +88,294 @@ pub fn copy_peer_ip(
 ) -> Result<(), AppError> {
   validate_peer_ip(peer_ip).map_err(AppError::Peer)?;
 
-  let clip_ip = copy_and_get(peer_ip).map_err(|e| {
-    error!("Failed to copy IP to clipboard: {e}");
+  let summary =
+    format!("Copied {} IP address", if host { "host" } else { "peer" });
+  copy_text(peer_ip, &summary, notif_body)
+}
+
+/// Copies arbitrary text to the clipboard and notifies the user
+///
+/// # Arguments
+/// * `text` - The text to copy
+/// * `summary` - The notification summary
+/// * `notif_body` - The notification message body
+pub fn copy_text(
+  text: &str,
+  summary: &str,
+  notif_body: &str,
+) -> Result<(), AppError> {
+  let clip_text = copy_and_get(text).map_err(|e| {
+    error!("Failed to copy to clipboard: {e}");
     AppError::Peer(PeerError::ClipboardError(e.to_string()))
   })?;
 
-  if clip_ip != peer_ip {
+  if clip_text != text {
     error!(
-      "Clipboard verification failed: expected '{peer_ip}', got '{clip_ip}'"
+      "Clipboard verification failed: expected '{text}', got '{clip_text}'"
     );
     return Err(AppError::Peer(PeerError::VerificationError(
-      "Clipboard content doesn't match the copied IP".into(),
+      "Clipboard content doesn't match the copied text".into(),
     )));
   }
 
-  let summary =
-    format!("Copied {} IP address", if host { "host" } else { "peer" });
-  info!("{summary} {clip_ip} to clipboard");
-
-  Notification::new()
-    .summary(&summary)
-    .body(notif_body)
-    .icon("tailscale")
-    .timeout(3000)
-    .show()
-    .map_err(|e| {
-      error!("Failed to show notification: {e}");
-      AppError::Peer(PeerError::NotificationError(e.to_string()))
-    })?;
+  info!("{summary} {clip_text} to clipboard");
+  notify(Category::Copy, summary, notif_body, "tailscale")
+}
 
-  Ok(())
+/// Pings `peer` once over Tailscale and reports the result
+pub fn ping(peer: &Machine) -> Result<(), AppError> {
+  let address = peer.address();
+  let name = &peer.display_name;
+
+  match run_with_elevation("tailscale", &["ping", "--c=1", &address]) {
+    Ok(output) => {
+      info!("Pinged {name}: {}", output.trim());
+      notify(
+        Category::Peer,
+        &format!("Ping {name}"),
+        output.trim(),
+        "tailscale",
+      )
+    },
+    Err(e) => {
+      error!("Failed to ping {name}: {e}");
+      notify(
+        Category::Peer,
+        &format!("Ping {name} failed"),
+        &e.to_string(),
+        "error",
+      )?;
+      Err(AppError::Peer(PeerError::CommandFailed(e.to_string())))
+    },
+  }
+}
+
+/// Lets the user pick a file and sends it to `peer` via Taildrop
+pub fn send_file(peer: &Machine) -> Result<(), AppError> {
+  let name = &peer.display_name;
+  let file = match choose_file() {
+    Ok(Some(file)) => file,
+    Ok(None) => {
+      info!("Taildrop to {name} cancelled");
+      return Ok(());
+    },
+    Err(e) => {
+      error!("Failed to choose a file for Taildrop: {e}");
+      notify(
+        Category::Taildrop,
+        "Taildrop failed",
+        &e.to_string(),
+        "error",
+      )?;
+      return Err(AppError::Peer(e));
+    },
+  };
+
+  let path = file.to_string_lossy();
+  let target = format!("{}:", peer.address());
+  match run_with_elevation("tailscale", &["file", "cp", &path, &target]) {
+    Ok(_) => {
+      let file_name = file
+        .file_name()
+        .map_or(path.clone(), |n| n.to_string_lossy());
+      info!("Sent {path} to {name} via Taildrop");
+      notify(
+        Category::Taildrop,
+        "Taildrop",
+        &format!("Sent {file_name} to {name}"),
+        "tailscale",
+      )
+    },
+    Err(e) => {
+      error!("Failed to send {path} to {name}: {e}");
+      notify(
+        Category::Taildrop,
+        "Taildrop failed",
+        &e.to_string(),
+        "error",
+      )?;
+      Err(AppError::Peer(PeerError::CommandFailed(e.to_string())))
+    },
+  }
+}
+
+/// Shows a notification summarizing everything known about `peer`
+pub fn show_details(peer: &Machine) -> Result<(), AppError> {
+  let summary = peer.display_name.to_string();
+  notification::show_for(
+    Category::Peer,
+    &summary,
+    &details(peer),
+    "tailscale",
+    10000,
+  )
+  .map_err(|e| {
+    error!("Failed to show notification: {e}");
+    AppError::Peer(PeerError::NotificationError(e.to_string()))
+  })
+}
+
+/// Formats the details of `peer` as one fact per line
+pub fn details(peer: &Machine) -> String {
+  let mut lines = Vec::new();
+
+  if let Some(dns_name) = peer.magic_dns_name() {
+    lines.push(format!("MagicDNS: {dns_name}"));
+  }
+  let ips: Vec<_> = peer.ips.iter().map(ToString::to_string).collect();
+  lines.push(format!("Addresses: {}", ips.join(", ")));
+  if !peer.os.is_empty() {
+    lines.push(format!("OS: {}", peer.os));
+  }
+  if !peer.tags.is_empty() {
+    lines.push(format!("Tags: {}", peer.tags.join(", ")));
+  }
+
+  if peer.online {
+    lines.push(match peer.cur_addr {
+      Some(addr) => format!("Connection: direct ({addr})"),
+      None if !peer.relay.is_empty() => {
+        format!("Connection: relayed via {}", peer.relay)
+      },
+      None => "Connection: online".to_owned(),
+    });
+  } else if let Some(last_seen) = peer.last_seen {
+    lines.push(format!(
+      "Last seen: {}",
+      last_seen.strftime("%Y-%m-%d %H:%M UTC")
+    ));
+  } else {
+    lines.push("Connection: offline".to_owned());
+  }
+
+  if peer.rx_bytes > 0 || peer.tx_bytes > 0 {
+    lines.push(format!(
+      "Traffic: {} received, {} sent",
+      format_bytes(peer.rx_bytes),
+      format_bytes(peer.tx_bytes)
+    ));
+  }
+  if let Some(expiry) = peer.key_expiry {
+    lines.push(format!("Key expiry: {}", expiry.strftime("%Y-%m-%d")));
+  }
+
+  lines.join("\n")
+}
+
+/// Formats a byte count using binary units
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+  const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+
+  if unit == 0 {
+    format!("{bytes} B")
+  } else {
+    format!("{value:.1} {}", UNITS[unit])
+  }
+}
+
+/// Asks the user to pick a file with the first available file chooser
+///
+/// Returns `Ok(None)` if the user dismissed the dialog.
+fn choose_file() -> Result<Option<PathBuf>, PeerError> {
+  let Some((program, args)) = FILE_CHOOSERS
+    .iter()
+    .find(|(program, _)| which::which(program).is_ok())
+  else {
+    return Err(PeerError::NoFileChosen(
+      "no file chooser found, install zenity or kdialog".into(),
+    ));
+  };
+
+  let output = Command::new(program).args(*args).output().map_err(|e| {
+    PeerError::NoFileChosen(format!("failed to run {program}: {e}"))
+  })?;
+
+  // Both choosers exit with status 1 when the dialog is cancelled
+  if !output.status.success() {
+    return Ok(None);
+  }
+
+  let path = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+  Ok((!path.is_empty()).then(|| PathBuf::from(path)))
+}
+
+/// Shows a desktop notification
+fn notify(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+) -> Result<(), AppError> {
+  notification::show(category, summary, body, icon).map_err(|e| {
+    error!("Failed to show notification: {e}");
+    AppError::Peer(PeerError::NotificationError(e.to_string()))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("testdata/status.json");
+
+  fn details_of(host: &str) -> String {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let peer = status
+      .peers
+      .values()
+      .find(|peer| peer.hostname == host)
+      .unwrap_or_else(|| panic!("peer {host} missing from fixture"));
+    details(peer)
+  }
+
+  #[test]
+  fn online_peer_details_show_connection_and_traffic() {
+    assert_eq!(
+      details_of("nas"),
+      [
+        "MagicDNS: nas.tail2f4d1.ts.net",
+        "Addresses: 100.88.12.4, fd7a:115c:a1e0::c301:c04",
+        "OS: linux",
+        "Connection: direct (192.168.1.10:41641)",
+        "Traffic: 1.8 MiB received, 2.1 MiB sent",
+        "Key expiry: 2025-05-17",
+      ]
+      .join("\n")
+    );
+  }
+
+  #[test]
+  fn offline_peer_details_show_last_seen() {
+    assert_eq!(
+      details_of("build-runner-01"),
+      [
+        "MagicDNS: build-runner-01.tail2f4d1.ts.net",
+        "Addresses: 100.73.201.55, fd7a:115c:a1e0::9d01:c937",
+        "OS: linux",
+        "Tags: tag:ci, tag:server",
+        "Last seen: 2024-11-19 07:44 UTC",
+      ]
+      .join("\n")
+    );
+    assert!(details_of("printer-gw").ends_with("Connection: offline"));
+  }
+
+  #[test]
+  fn bytes_are_formatted_with_binary_units() {
+    assert_eq!(format_bytes(0), "0 B");
+    assert_eq!(format_bytes(1023), "1023 B");
+    assert_eq!(format_bytes(1024), "1.0 KiB");
+    assert_eq!(format_bytes(1_843_216), "1.8 MiB");
+    assert_eq!(format_bytes(5 * 1024_u64.pow(3)), "5.0 GiB");
+    assert_eq!(format_bytes(3 * 1024_u64.pow(5)), "3072.0 TiB");
+  }
 }
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// The subset of tailscaled's preferences shown in the tray
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Prefs {
+  #[serde(rename = "ExitNodeID", default)]
+  pub exit_node_id: String,
+
+  #[serde(rename = "ExitNodeAllowLANAccess", default)]
+  pub exit_node_allow_lan_access: bool,
+}
@@ -4,9 +4,11 @@ use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+  config,
   error::AppError,
-  svg::renderer::Theme,
   tailscale::{
+    localapi::{LocalApi, LocalApiError},
+    prefs::Prefs,
     utils,
     utils::{Machine, User},
   },
@@ -41,7 +43,7 @@ impl fmt::Display for StatusError {
 impl Error for StatusError {}
 
 /// Represents the complete status of the Tailscale network
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Status {
   // Runtime status (not from JSON)
   #[serde(skip)]
@@ -62,15 +64,111 @@ pub struct Status {
   #[serde(rename = "User", default)]
   user: HashMap<String, User>,
 
+  #[serde(rename = "Health", default, deserialize_with = "utils::nullable")]
+  health: Vec<String>,
+
+  #[serde(rename = "CurrentTailnet", default)]
+  current_tailnet: Option<Tailnet>,
+
   // Catch all other fields we might not know about
   #[serde(flatten)]
   extra: HashMap<String, serde_json::Value>,
 }
 
-/// Gets the current Tailscale status
+/// The tailnet this device belongs to
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct Tailnet {
+  #[serde(rename = "Name", default)]
+  name: String,
+}
+
+impl Status {
+  /// Returns the MagicDNS suffix of the tailnet this device belongs to
+  pub fn magic_dns_suffix(&self) -> &str {
+    self.magic_dnssuffix.trim_matches('.')
+  }
+
+  /// Returns the display name of the user with `id`, falling back to their
+  /// login name
+  pub fn user_name(&self, id: u64) -> Option<&str> {
+    self.user.values().find(|user| user.id == id).map(|user| {
+      if user.display_name.is_empty() {
+        user.login_name.as_str()
+      } else {
+        user.display_name.as_str()
+      }
+    })
+  }
+
+  /// Returns tailscaled's backend state, such as `Running` or `Stopped`
+  pub fn backend_state(&self) -> &str {
+    &self.backend_state
+  }
+
+  /// Returns the name of the tailnet this device belongs to, if known
+  pub fn tailnet_name(&self) -> Option<&str> {
+    self
+      .current_tailnet
+      .as_ref()
+      .map(|tailnet| tailnet.name.as_str())
+      .filter(|name| !name.is_empty())
+  }
+
+  /// Returns the health warnings reported by tailscaled
+  pub fn health(&self) -> &[String] {
+    &self.health
+  }
+
+  /// Returns the peer currently used as exit node, if any
+  pub fn exit_node(&self) -> Option<&Machine> {
+    self.peers.values().find(|peer| peer.exit_node)
+  }
+
+  /// Finds the peer whose name, hostname, MagicDNS name or Tailscale IP
+  /// matches `query`, ignoring case
+  pub fn find_peer(&self, query: &str) -> Option<&Machine> {
+    self.peers.values().find(|peer| peer.matches(query))
+  }
+
+  /// Returns whether `other` would be presented identically in the tray,
+  /// ignoring fields the tray does not display
+  ///
+  /// The tailnet, users and MagicDNS suffix are compared as well, since the
+  /// tooltip and peer menus show them.
+  pub fn same_state(&self, other: &Self) -> bool {
+    self.tailscale_up == other.tailscale_up
+      && self.backend_state == other.backend_state
+      && self.health == other.health
+      && self.magic_dnssuffix == other.magic_dnssuffix
+      && self.tailnet_name() == other.tailnet_name()
+      && self.user == other.user
+      && self.this_machine.same_state(&other.this_machine)
+      && self.peers.len() == other.peers.len()
+      && self.peers.iter().all(|(key, peer)| {
+        other.peers.get(key).is_some_and(|p| peer.same_state(p))
+      })
+  }
+}
+
+/// Gets the current Tailscale status, preferring the LocalAPI socket and
+/// falling back to the tailscale command when the socket cannot be reached
 pub fn get() -> Result<Status, Box<dyn Error>> {
-  let status_json = get_json()?;
-  let mut status: Status = serde_json::from_str(&status_json).map_err(|e| {
+  match LocalApi::default().status() {
+    Ok(status) => Ok(status),
+    Err(LocalApiError::Connect(e)) => {
+      debug!("LocalAPI unavailable ({e}), falling back to tailscale CLI");
+      Ok(parse(&get_json()?)?)
+    },
+    Err(e) => {
+      error!("Failed to query LocalAPI status: {e}");
+      Err(AppError::from(e).into())
+    },
+  }
+}
+
+/// Parses the JSON status document returned by tailscaled
+pub fn parse(status_json: &str) -> Result<Status, StatusError> {
+  let mut status: Status = serde_json::from_str(status_json).map_err(|e| {
     error!("Failed to parse Tailscale status: {e}");
     StatusError::ParseFailed(format!("{e}: {status_json}"))
   })?;
@@ -97,10 +195,17 @@ pub fn get_current() -> Result<Context, AppError> {
     )));
   }
 
+  // Prefs are only used for display, so a missing socket is not fatal
+  let prefs = LocalApi::default().prefs().unwrap_or_else(|e| {
+    debug!("Failed to read prefs, using defaults: {e}");
+    Prefs::default()
+  });
+
   Ok(Context {
-    ip: status.this_machine.ips[0].clone(),
+    ip: status.this_machine.ips[0].to_string(),
     status,
-    theme: Theme::from_env(),
+    prefs,
+    config: config::current(),
   })
 }
 
@@ -1,9 +1,11 @@
 use std::{
   collections::HashSet,
   fmt::{Display, Formatter},
+  net::{IpAddr, SocketAddr},
 };
 
-use serde::{Deserialize, Serialize};
+use jiff::Timestamp;
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Clone)]
 pub enum PeerKind {
@@ -26,31 +28,161 @@ impl Display for PeerKind {
   }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Machine {
   #[serde(skip)]
   pub display_name: PeerKind,
 
+  #[serde(rename = "ID", default)]
+  pub id: String,
+
   #[serde(rename = "DNSName", default)]
   pub dns_name: String,
 
   #[serde(rename = "HostName", default)]
   pub hostname: String,
 
-  #[serde(rename = "TailscaleIPs", default)]
-  pub ips: Vec<String>,
+  #[serde(rename = "TailscaleIPs", default, deserialize_with = "nullable")]
+  pub ips: Vec<IpAddr>,
+
+  #[serde(rename = "Online", default, deserialize_with = "nullable")]
+  pub online: bool,
+
+  #[serde(rename = "OS", default)]
+  pub os: String,
+
+  #[serde(rename = "Tags", default, deserialize_with = "nullable")]
+  pub tags: Vec<String>,
+
+  #[serde(rename = "UserID", default)]
+  pub user_id: u64,
+
+  #[serde(rename = "LastSeen", default, deserialize_with = "go_time")]
+  pub last_seen: Option<Timestamp>,
+
+  #[serde(rename = "Relay", default)]
+  pub relay: String,
+
+  #[serde(rename = "CurAddr", default, deserialize_with = "socket_addr")]
+  pub cur_addr: Option<SocketAddr>,
+
+  #[serde(rename = "RxBytes", default)]
+  pub rx_bytes: u64,
+
+  #[serde(rename = "TxBytes", default)]
+  pub tx_bytes: u64,
+
+  #[serde(rename = "KeyExpiry", default, deserialize_with = "go_time")]
+  pub key_expiry: Option<Timestamp>,
+
+  #[serde(rename = "Active", default)]
+  pub active: bool,
+
+  #[serde(rename = "ExitNode", default)]
+  pub exit_node: bool,
+
+  #[serde(rename = "ExitNodeOption", default)]
+  pub exit_node_option: bool,
+
+  #[serde(rename = "PrimaryRoutes", default, deserialize_with = "nullable")]
+  pub primary_routes: Vec<String>,
+
+  #[serde(rename = "Capabilities", default, deserialize_with = "nullable")]
+  pub capabilities: Vec<String>,
+
+  #[serde(rename = "ShareeNode", default)]
+  pub sharee_node: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// Reachability of a machine as shown in the device menus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Presence {
+  /// Connected with an active session
+  Online,
+  /// Connected to the tailnet, but without recent traffic
+  Idle,
+  /// Not connected to the tailnet
+  Offline,
+}
+
+impl Presence {
+  /// Returns the marker shown next to a peer in the menu
+  pub const fn marker(self) -> &'static str {
+    match self {
+      Self::Online => "●",
+      Self::Idle => "◐",
+      Self::Offline => "○",
+    }
+  }
+}
+
+impl Machine {
+  /// Returns whether this machine is online, idle or offline
+  pub const fn presence(&self) -> Presence {
+    match (self.online, self.active) {
+      (false, _) => Presence::Offline,
+      (true, true) => Presence::Online,
+      (true, false) => Presence::Idle,
+    }
+  }
+
+  /// Returns the MagicDNS name without its trailing dot, if there is one
+  pub fn magic_dns_name(&self) -> Option<&str> {
+    let name = self.dns_name.trim_end_matches('.');
+    (!name.is_empty()).then_some(name)
+  }
+
+  /// Returns whether this machine's name, hostname, MagicDNS name or
+  /// Tailscale IP matches `query`, ignoring case
+  pub fn matches(&self, query: &str) -> bool {
+    let query = query.trim().trim_end_matches('.');
+    self.display_name.to_string().eq_ignore_ascii_case(query)
+      || self.hostname.eq_ignore_ascii_case(query)
+      || self
+        .magic_dns_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case(query))
+      || self.ips.iter().any(|ip| ip.to_string() == query)
+  }
+
+  /// Returns the name or address other programs should use to reach this
+  /// machine, preferring its MagicDNS name
+  pub fn address(&self) -> String {
+    self
+      .magic_dns_name()
+      .map(ToOwned::to_owned)
+      .or_else(|| self.ips.first().map(ToString::to_string))
+      .unwrap_or_else(|| self.hostname.clone())
+  }
+
+  /// Returns whether `other` would be presented identically in the tray
+  ///
+  /// Traffic counters, the current address and last-seen time change
+  /// constantly and are deliberately left out.
+  pub fn same_state(&self, other: &Self) -> bool {
+    self.dns_name == other.dns_name
+      && self.hostname == other.hostname
+      && self.ips == other.ips
+      && self.presence() == other.presence()
+      && self.os == other.os
+      && self.tags == other.tags
+      && self.user_id == other.user_id
+      && self.key_expiry == other.key_expiry
+      && self.exit_node == other.exit_node
+      && self.exit_node_option == other.exit_node_option
+      && self.sharee_node == other.sharee_node
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct User {
   #[serde(rename = "ID", default)]
-  id: u64,
+  pub id: u64,
 
   #[serde(rename = "LoginName", default)]
-  login_name: String,
+  pub login_name: String,
 
   #[serde(rename = "DisplayName", default)]
-  display_name: String,
+  pub display_name: String,
 
   #[serde(rename = "ProfilePicURL", default)]
   profile_pic_url: String,
@@ -63,6 +195,44 @@ pub struct User {
   extra: std::collections::HashMap<String, serde_json::Value>,
 }
 
+/// Deserializes a value tailscaled may send as `null` into its default
+pub(crate) fn nullable<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Deserialize<'de> + Default,
+{
+  Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Deserializes a Go `time.Time`, mapping its zero value to `None`
+fn go_time<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let Some(time) = Option::<String>::deserialize(deserializer)? else {
+    return Ok(None);
+  };
+
+  if time.is_empty() || time.starts_with("0001-01-01T") {
+    return Ok(None);
+  }
+
+  time.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Deserializes an `ip:port` string, mapping the empty string to `None`
+fn socket_addr<'de, D>(deserializer: D) -> Result<Option<SocketAddr>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  match Option::<String>::deserialize(deserializer)? {
+    Some(addr) if !addr.is_empty() => {
+      addr.parse().map(Some).map_err(serde::de::Error::custom)
+    },
+    _ => Ok(None),
+  }
+}
+
 pub fn trim_suffix(name: &str, suffix: &str) -> String {
   let name = name.trim_end_matches('.');
   let suffix = suffix.trim_matches('.');
@@ -113,3 +283,177 @@ pub fn set_display_name(m: &mut Machine, dns_suffix: &str) {
     m.display_name = PeerKind::HostName(dns_name);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("testdata/status.json");
+
+  fn peer<'a>(status: &'a status::Status, host: &str) -> &'a Machine {
+    status
+      .peers
+      .values()
+      .find(|peer| peer.hostname == host)
+      .unwrap_or_else(|| panic!("peer {host} missing from fixture"))
+  }
+
+  #[test]
+  fn self_record_is_parsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let this = &status.this_machine;
+
+    assert_eq!(this.id, "nRdkNQ9vBk11CNTRL");
+    assert_eq!(this.os, "linux");
+    assert!(this.online);
+    assert_eq!(this.ips, [
+      "100.101.102.103".parse::<IpAddr>().unwrap(),
+      "fd7a:115c:a1e0::e701:6667".parse::<IpAddr>().unwrap(),
+    ]);
+    assert_eq!(this.last_seen, None, "zero time should map to None");
+    assert_eq!(this.cur_addr, None, "empty address should map to None");
+    assert_eq!(
+      this.key_expiry,
+      Some("2025-08-29T11:48:10Z".parse::<Timestamp>().unwrap())
+    );
+    assert_eq!(this.capabilities.len(), 3);
+  }
+
+  #[test]
+  fn online_peer_record_is_parsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let nas = peer(&status, "nas");
+
+    assert!(nas.online);
+    assert!(nas.exit_node);
+    assert!(nas.exit_node_option);
+    assert!(!nas.sharee_node);
+    assert_eq!(nas.user_id, status.this_machine.user_id);
+    assert!(nas.tags.is_empty());
+    assert_eq!(nas.relay, "fra");
+    assert_eq!(nas.cur_addr, Some("192.168.1.10:41641".parse().unwrap()));
+    assert_eq!(nas.rx_bytes, 1_843_216);
+    assert_eq!(nas.tx_bytes, 2_209_784);
+    assert_eq!(
+      nas.last_seen,
+      Some("2024-11-20T18:01:12Z".parse::<Timestamp>().unwrap())
+    );
+    assert_eq!(nas.primary_routes, ["192.168.1.0/24"]);
+  }
+
+  #[test]
+  fn offline_tagged_peer_record_is_parsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let runner = peer(&status, "build-runner-01");
+
+    assert!(!runner.online);
+    assert!(!runner.exit_node_option);
+    assert_eq!(runner.tags, ["tag:ci", "tag:server"]);
+    assert_ne!(runner.user_id, status.this_machine.user_id);
+    assert_eq!(runner.key_expiry, None, "missing expiry should map to None");
+    assert!(
+      runner.capabilities.is_empty(),
+      "missing list should be empty"
+    );
+    assert!(runner.primary_routes.is_empty());
+  }
+
+  #[test]
+  fn expired_shared_peer_record_is_parsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let macbook = peer(&status, "bobs-macbook");
+
+    assert!(macbook.online);
+    assert!(!macbook.sharee_node);
+    assert_eq!(macbook.os, "macOS");
+    assert_eq!(status.user_name(macbook.user_id), Some("Bob"));
+    assert_eq!(
+      macbook.key_expiry,
+      Some("2024-08-10T16:03:27Z".parse::<Timestamp>().unwrap())
+    );
+    assert_eq!(
+      macbook.last_seen,
+      Some("2024-11-20T17:30:02Z".parse::<Timestamp>().unwrap())
+    );
+  }
+
+  #[test]
+  fn sharee_peer_record_is_parsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let phone = peer(&status, "carols-phone");
+
+    assert!(phone.sharee_node);
+    assert!(!phone.online);
+    assert_eq!(status.user_name(phone.user_id), Some("Carol"));
+  }
+
+  #[test]
+  fn tagged_peer_never_seen_is_parsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let printer = peer(&status, "printer-gw");
+
+    assert!(!printer.online);
+    assert_eq!(printer.last_seen, None, "zero time should map to None");
+    assert_eq!(printer.tags, ["tag:iot"]);
+    assert_eq!(printer.key_expiry, None);
+  }
+
+  #[test]
+  fn peers_are_found_by_name_or_address() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let found = |query| status.find_peer(query).map(|peer| &peer.hostname);
+
+    assert_eq!(found("nas").map(String::as_str), Some("nas"));
+    assert_eq!(
+      found("NAS.tail2f4d1.ts.net.").map(String::as_str),
+      Some("nas")
+    );
+    assert_eq!(
+      found("100.73.201.55").map(String::as_str),
+      Some("build-runner-01")
+    );
+    assert_eq!(found("framework"), None, "self is not a peer");
+    assert_eq!(
+      status.exit_node().map(|peer| peer.hostname.as_str()),
+      Some("nas")
+    );
+  }
+
+  #[test]
+  fn same_state_ignores_traffic_but_not_identity() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let reparsed = |from: &str, to: &str| {
+      status::parse(&STATUS_JSON.replace(from, to)).expect("should parse")
+    };
+
+    let traffic = reparsed(r#""RxBytes": 1843216"#, r#""RxBytes": 9999999"#);
+    assert!(status.same_state(&traffic));
+
+    let tailnet = reparsed(r#""Name": "alice@example.com""#, r#""Name": "b""#);
+    assert!(!status.same_state(&tailnet));
+
+    let user = reparsed(r#""DisplayName": "Alice""#, r#""DisplayName": "A""#);
+    assert!(!status.same_state(&user));
+
+    let suffix = reparsed(
+      r#""MagicDNSSuffix": "tail2f4d1.ts.net","#,
+      r#""MagicDNSSuffix": "tail0000.ts.net","#,
+    );
+    assert!(!status.same_state(&suffix));
+  }
+
+  #[test]
+  fn null_lists_deserialize_as_empty() {
+    let machine: Machine = serde_json::from_str(
+      r#"{"TailscaleIPs": null, "Tags": null, "CurAddr": "", "LastSeen": null, "Online": null}"#,
+    )
+    .expect("nulls should be accepted");
+
+    assert!(!machine.online);
+    assert!(machine.ips.is_empty());
+    assert!(machine.tags.is_empty());
+    assert_eq!(machine.cur_addr, None);
+    assert_eq!(machine.last_seen, None);
+  }
+}
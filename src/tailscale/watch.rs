@@ -0,0 +1,101 @@
+use std::{
+  io::{self, BufRead, BufReader},
+  os::unix::net::UnixStream,
+  time::Duration,
+};
+
+use log::debug;
+use serde::{Deserialize, de::IgnoredAny};
+
+use crate::tailscale::localapi::{LocalApi, LocalApiError};
+
+/// Send the current backend state as the first notification
+pub const NOTIFY_INITIAL_STATE: u64 = 1 << 1;
+/// Strip private keys from any network map that gets sent
+pub const NOTIFY_NO_PRIVATE_KEYS: u64 = 1 << 4;
+/// Let tailscaled coalesce bursts of network map updates
+pub const NOTIFY_RATE_LIMIT: u64 = 1 << 8;
+
+/// A single message from tailscaled's IPN notification bus
+///
+/// Only the parts the tray reacts to are modelled; payloads such as the
+/// network map are skipped since a fresh status is fetched afterwards.
+#[derive(Deserialize, Debug, Default)]
+pub struct Notify {
+  #[serde(rename = "ErrMessage", default)]
+  pub err_message: Option<String>,
+
+  #[serde(rename = "State", default)]
+  pub state: Option<u8>,
+
+  #[serde(rename = "BrowseToURL", default)]
+  pub browse_to_url: Option<String>,
+
+  #[serde(rename = "Prefs", default)]
+  prefs: Option<IgnoredAny>,
+
+  #[serde(rename = "NetMap", default)]
+  net_map: Option<IgnoredAny>,
+
+  #[serde(rename = "Health", default)]
+  health: Option<IgnoredAny>,
+}
+
+impl Notify {
+  /// Returns whether this notification may have changed what the tray shows
+  pub const fn changes_status(&self) -> bool {
+    self.state.is_some()
+      || self.prefs.is_some()
+      || self.net_map.is_some()
+      || self.health.is_some()
+  }
+}
+
+/// A subscription to tailscaled's `watch-ipn-bus` stream
+///
+/// Iterating yields notifications as they arrive and ends when tailscaled
+/// closes the connection.
+pub struct IpnBus {
+  reader: BufReader<UnixStream>,
+  line:   String,
+}
+
+impl IpnBus {
+  /// Subscribes to the notification bus with the given `NOTIFY_*` mask
+  pub fn watch(api: &LocalApi, mask: u64) -> Result<Self, LocalApiError> {
+    let reader = api.stream(&format!("watch-ipn-bus?mask={mask}"))?;
+    debug!("Subscribed to IPN bus with mask {mask:#x}");
+
+    Ok(Self {
+      reader,
+      line: String::new(),
+    })
+  }
+
+  /// Makes iterating give up with an error after waiting `timeout` for the
+  /// next notification
+  pub fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+    self.reader.get_ref().set_read_timeout(Some(timeout))
+  }
+}
+
+impl Iterator for IpnBus {
+  type Item = Result<Notify, LocalApiError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      self.line.clear();
+      match self.reader.read_line(&mut self.line) {
+        Ok(0) => return None,
+        Ok(_) if self.line.trim().is_empty() => {},
+        Ok(_) => {
+          return Some(
+            serde_json::from_str(&self.line)
+              .map_err(|e| LocalApiError::Parse(e.to_string())),
+          );
+        },
+        Err(e) => return Some(Err(e.into())),
+      }
+    }
+  }
+}
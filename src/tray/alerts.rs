@@ -0,0 +1,660 @@
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{
+    OnceLock,
+    mpsc::{self, Receiver, RecvTimeoutError, Sender},
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use jiff::{SignedDuration, Timestamp};
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::{
+  config::{self, NotificationConfig, deserialize_seconds},
+  notification::{self, Action, Category},
+  tailscale::status::Status,
+  tray::tooltip,
+};
+
+/// How often key expiries are re-checked while no status arrives
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Feeds status snapshots to the alert thread, once [`start`] was called
+static SENDER: OnceLock<Sender<Status>> = OnceLock::new();
+
+/// Settings for notifications about peers coming online or going offline
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerAlertConfig {
+  /// Peers to watch, by name, hostname, MagicDNS name or IP, or `*` for all
+  pub watch:             Vec<String>,
+  /// How long a peer must stay in its new state before it is reported
+  #[serde(deserialize_with = "deserialize_seconds")]
+  pub debounce:          Duration,
+  /// Number of simultaneous changes reported as a single summary
+  pub summary_threshold: usize,
+}
+
+impl Default for PeerAlertConfig {
+  fn default() -> Self {
+    Self {
+      watch:             Vec::new(),
+      debounce:          Duration::from_secs(30),
+      summary_threshold: 3,
+    }
+  }
+}
+
+impl PeerAlertConfig {
+  /// Returns whether the peer described by `matches` is on the watch list
+  fn watches(&self, matches: impl Fn(&str) -> bool) -> bool {
+    self
+      .watch
+      .iter()
+      .any(|entry| entry.trim() == "*" || matches(entry))
+  }
+}
+
+/// Settings for warnings about node keys that are about to expire
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpiryAlertConfig {
+  /// Days before the expiry at which to warn, besides the expiry itself
+  pub warn_days: Vec<u32>,
+}
+
+impl Default for ExpiryAlertConfig {
+  fn default() -> Self {
+    Self {
+      warn_days: vec![14, 3, 1],
+    }
+  }
+}
+
+impl ExpiryAlertConfig {
+  /// Returns how many warnings are due for a key expiring at `expiry`: one
+  /// per threshold passed by `now`, and one more once it expired
+  fn stage(&self, expiry: Timestamp, now: Timestamp) -> usize {
+    let remaining = expiry.duration_since(now);
+    if remaining <= SignedDuration::ZERO {
+      return self.warn_days.len() + 1;
+    }
+
+    self
+      .warn_days
+      .iter()
+      .filter(|&&days| {
+        remaining <= SignedDuration::from_hours(i64::from(days) * 24)
+      })
+      .count()
+  }
+
+  /// Describes when `expiry` is, if it is close enough to warn about
+  pub fn notice(
+    &self,
+    expiry: Option<Timestamp>,
+    now: Timestamp,
+  ) -> Option<String> {
+    let expiry = expiry?;
+    (self.stage(expiry, now) > 0).then(|| expiry_phrase(expiry, now))
+  }
+}
+
+/// A watched peer that came online or went offline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerChange {
+  pub name:   String,
+  pub online: bool,
+}
+
+/// A watched peer as seen in the latest snapshot
+#[derive(Debug)]
+struct Observed {
+  name:   String,
+  online: bool,
+  /// When the peer first differed from its reported state, if it does
+  since:  Option<Instant>,
+}
+
+/// Tracks the online state of watched peers across status snapshots
+#[derive(Debug, Default)]
+pub struct PeerTracker {
+  /// Online state last reported, or first seen, by node key
+  reported: HashMap<String, bool>,
+  /// Watched peers in the latest snapshot, by node key
+  observed: HashMap<String, Observed>,
+}
+
+impl PeerTracker {
+  /// Records the watched peers of `status`, seen at `now`
+  ///
+  /// Peers seen for the first time set the baseline without being reported.
+  /// While this device is not connected, every peer looks offline, so the
+  /// tracker starts over instead.
+  pub fn observe(
+    &mut self,
+    status: &Status,
+    config: &PeerAlertConfig,
+    now: Instant,
+  ) {
+    if status.backend_state() != "Running" {
+      self.reported.clear();
+      self.observed.clear();
+      return;
+    }
+
+    let previous = std::mem::take(&mut self.observed);
+    for (key, peer) in &status.peers {
+      if !config.watches(|query| peer.matches(query)) {
+        continue;
+      }
+
+      let reported = *self.reported.entry(key.clone()).or_insert(peer.online);
+      // A peer flapping back to its reported state restarts the debounce
+      let since = (peer.online != reported).then(|| {
+        previous
+          .get(key)
+          .filter(|seen| seen.online == peer.online)
+          .and_then(|seen| seen.since)
+          .unwrap_or(now)
+      });
+      self.observed.insert(key.clone(), Observed {
+        name: peer.display_name.to_string(),
+        online: peer.online,
+        since,
+      });
+    }
+
+    // Forget peers that left the tailnet or the watch list
+    self
+      .reported
+      .retain(|key, _| self.observed.contains_key(key));
+  }
+
+  /// Returns the changes that lasted for at least `debounce` by `now`,
+  /// ordered by name, and marks them as reported
+  pub fn settle(
+    &mut self,
+    debounce: Duration,
+    now: Instant,
+  ) -> Vec<PeerChange> {
+    let mut changes = Vec::new();
+    for (key, peer) in &mut self.observed {
+      if peer
+        .since
+        .is_some_and(|since| now.duration_since(since) >= debounce)
+      {
+        peer.since = None;
+        self.reported.insert(key.clone(), peer.online);
+        changes.push(PeerChange {
+          name:   peer.name.clone(),
+          online: peer.online,
+        });
+      }
+    }
+
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    changes
+  }
+
+  /// Returns when the earliest pending change will have lasted `debounce`
+  pub fn next_deadline(&self, debounce: Duration) -> Option<Instant> {
+    self
+      .observed
+      .values()
+      .filter_map(|peer| peer.since)
+      .min()
+      .map(|since| since + debounce)
+  }
+}
+
+/// A key that passed another warning threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiryWarning {
+  pub name:        String,
+  pub this_device: bool,
+  pub expiry:      Timestamp,
+}
+
+/// Tracks which expiry warnings were shown for this device and watched peers
+#[derive(Debug, Default)]
+pub struct ExpiryTracker {
+  /// Expiry and number of warnings shown for it, by machine ID
+  warned: HashMap<String, (Timestamp, usize)>,
+}
+
+impl ExpiryTracker {
+  /// Returns the warnings that became due by `now`, at most one per key
+  ///
+  /// A key that already passed several thresholds when first seen gets a
+  /// single warning, and a renewed key starts over.
+  pub fn check(
+    &mut self,
+    status: &Status,
+    config: &NotificationConfig,
+    now: Timestamp,
+  ) -> Vec<ExpiryWarning> {
+    let peers = status
+      .peers
+      .values()
+      .filter(|peer| config.peers.watches(|query| peer.matches(query)));
+    let machines = [(&status.this_machine, true)]
+      .into_iter()
+      .chain(peers.map(|peer| (peer, false)));
+
+    let mut warnings = Vec::new();
+    for (machine, this_device) in machines {
+      let Some(expiry) = machine.key_expiry else {
+        continue;
+      };
+
+      let stage = config.expiry.stage(expiry, now);
+      let warned = self.warned.entry(machine.id.clone()).or_insert((expiry, 0));
+      if warned.0 != expiry {
+        *warned = (expiry, 0);
+      }
+      if stage > warned.1 {
+        warned.1 = stage;
+        warnings.push(ExpiryWarning {
+          name: machine.display_name.to_string(),
+          this_device,
+          expiry,
+        });
+      }
+    }
+    warnings
+  }
+}
+
+/// Tracks which health warnings were already notified about
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+  /// Warnings present in the latest snapshot
+  known: HashSet<String>,
+}
+
+impl HealthTracker {
+  /// Returns the warnings of `status` that were not present before
+  ///
+  /// A warning that clears and comes back later counts as new again.
+  pub fn check(&mut self, status: &Status) -> Vec<String> {
+    let current: HashSet<_> = status.health().iter().cloned().collect();
+    let new = status
+      .health()
+      .iter()
+      .filter(|warning| !self.known.contains(*warning))
+      .cloned()
+      .collect();
+    self.known = current;
+    new
+  }
+}
+
+/// Starts the thread that turns status snapshots into notifications, with
+/// `initial` as the first one
+pub fn start(initial: Status) {
+  let (tx, rx) = mpsc::channel();
+  let _ = tx.send(initial);
+  if SENDER.set(tx).is_err() {
+    return;
+  }
+  thread::spawn(move || run(&rx));
+}
+
+/// Hands `status` to the alert thread
+///
+/// Does nothing unless the thread was started.
+pub fn observe(status: &Status) {
+  if let Some(sender) = SENDER.get() {
+    let _ = sender.send(status.clone());
+  }
+}
+
+/// Observes every snapshot received on `rx`, notifying about the peer
+/// changes that outlast the debounce and about expiring keys, until the
+/// sender goes away
+fn run(rx: &Receiver<Status>) {
+  let mut peers = PeerTracker::default();
+  let mut expiry = ExpiryTracker::default();
+  let mut health = HealthTracker::default();
+  let mut latest: Option<Status> = None;
+
+  loop {
+    // Settings are re-read so that a reloaded configuration applies at once
+    let config = config::current().notifications.clone();
+    let expiry_check = latest
+      .as_ref()
+      .map(|_| Instant::now() + EXPIRY_CHECK_INTERVAL);
+    let received = match peers
+      .next_deadline(config.peers.debounce)
+      .into_iter()
+      .chain(expiry_check)
+      .min()
+    {
+      Some(deadline) => {
+        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+      },
+      None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+
+    match received {
+      Ok(status) => {
+        peers.observe(&status, &config.peers, Instant::now());
+        for warning in health.check(&status) {
+          notify_health(&warning);
+        }
+        latest = Some(status);
+      },
+      Err(RecvTimeoutError::Timeout) => {},
+      Err(RecvTimeoutError::Disconnected) => return,
+    }
+
+    let changes = peers.settle(config.peers.debounce, Instant::now());
+    if !changes.is_empty() {
+      notify_peers(&changes, config.peers.summary_threshold);
+    }
+
+    if let Some(status) = &latest {
+      let now = Timestamp::now();
+      for warning in expiry.check(status, &config, now) {
+        warn_expiry(&warning, now);
+      }
+    }
+  }
+}
+
+/// Shows the notification for a new health `warning`
+fn notify_health(warning: &str) {
+  warn!("tailscaled reported a health warning: {warning}");
+  if let Err(e) = notification::show(
+    Category::Health,
+    "Tailscale health warning",
+    warning,
+    "dialog-warning",
+  ) {
+    warn!("Failed to show health notification: {e}");
+  }
+}
+
+/// Describes `expiry` as "expires in 3 days" or "expired 2 hours ago"
+fn expiry_phrase(expiry: Timestamp, now: Timestamp) -> String {
+  let countdown = tooltip::countdown(expiry, now);
+  if countdown.starts_with("expired") {
+    countdown
+  } else {
+    format!("expires {countdown}")
+  }
+}
+
+/// Shows the notification for `warning`, offering to re-authenticate when
+/// the key is this device's own
+fn warn_expiry(warning: &ExpiryWarning, now: Timestamp) {
+  let phrase = expiry_phrase(warning.expiry, now);
+  warn!("Key of {} {phrase}", warning.name);
+
+  let result = if warning.this_device {
+    notification::show_with_actions(
+      Category::Expiry,
+      &format!("Tailscale key {phrase}"),
+      "Re-authenticate this device to stay connected to your tailnet.",
+      "dialog-warning",
+      vec![(Action::Reauthenticate, "Re-authenticate")],
+    )
+  } else {
+    notification::show(
+      Category::Expiry,
+      &format!("Key of {} {phrase}", warning.name),
+      "Re-authenticate it or disable its key expiry in the admin console.",
+      "dialog-warning",
+    )
+  };
+  if let Err(e) = result {
+    warn!("Failed to show expiry notification: {e}");
+  }
+}
+
+/// Shows one notification per change, or a single summary when at least
+/// `summary_threshold` peers changed at once
+fn notify_peers(changes: &[PeerChange], summary_threshold: usize) {
+  for change in changes {
+    info!(
+      "Peer {} is now {}",
+      change.name,
+      if change.online { "online" } else { "offline" }
+    );
+  }
+
+  let notifications = if changes.len() >= summary_threshold.max(2) {
+    vec![summarize(changes)]
+  } else {
+    changes
+      .iter()
+      .map(|change| {
+        if change.online {
+          (format!("{} is online", change.name), String::new(), true)
+        } else {
+          (
+            format!("{} went offline", change.name),
+            String::new(),
+            false,
+          )
+        }
+      })
+      .collect()
+  };
+
+  for (summary, body, online) in notifications {
+    let icon = if online {
+      "network-transmit-receive"
+    } else {
+      "network-offline"
+    };
+    if let Err(e) = notification::show(Category::Peer, &summary, &body, icon) {
+      warn!("Failed to show peer notification: {e}");
+    }
+  }
+}
+
+/// Describes many changes at once, returning the summary, body and whether
+/// no peer went offline
+fn summarize(changes: &[PeerChange]) -> (String, String, bool) {
+  let names = |online: bool| {
+    changes
+      .iter()
+      .filter(|change| change.online == online)
+      .map(|change| change.name.as_str())
+      .collect::<Vec<_>>()
+  };
+  let (online, offline) = (names(true), names(false));
+  debug!(
+    "Summarizing {} online and {} offline peers",
+    online.len(),
+    offline.len()
+  );
+
+  let body = [("Online", &online), ("Offline", &offline)]
+    .into_iter()
+    .filter(|(_, names)| !names.is_empty())
+    .map(|(label, names)| format!("{label}: {}", names.join(", ")))
+    .collect::<Vec<_>>()
+    .join("\n");
+
+  (
+    format!("{} peers changed state", changes.len()),
+    body,
+    offline.is_empty(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("../tailscale/testdata/status.json");
+
+  fn config(watch: &[&str]) -> PeerAlertConfig {
+    PeerAlertConfig {
+      watch: watch.iter().map(|&entry| entry.to_owned()).collect(),
+      ..PeerAlertConfig::default()
+    }
+  }
+
+  fn with_nas_online(online: bool) -> Status {
+    let mut status = status::parse(STATUS_JSON).expect("fixture should parse");
+    for peer in status.peers.values_mut() {
+      if peer.hostname == "nas" {
+        peer.online = online;
+      }
+    }
+    status
+  }
+
+  #[test]
+  fn changes_are_reported_once_they_outlast_the_debounce() {
+    let config = config(&["nas.tail2f4d1.ts.net"]);
+    let debounce = config.debounce;
+    let start = Instant::now();
+    let mut tracker = PeerTracker::default();
+
+    tracker.observe(&with_nas_online(true), &config, start);
+    assert!(tracker.settle(debounce, start).is_empty());
+
+    let offline_at = start + Duration::from_secs(5);
+    tracker.observe(&with_nas_online(false), &config, offline_at);
+    assert_eq!(tracker.next_deadline(debounce), Some(offline_at + debounce));
+    assert!(tracker.settle(debounce, offline_at).is_empty());
+
+    // A later snapshot in the same state keeps the original start
+    tracker.observe(&with_nas_online(false), &config, offline_at + debounce);
+    assert_eq!(tracker.settle(debounce, offline_at + debounce), [
+      PeerChange {
+        name:   "nas".to_owned(),
+        online: false,
+      }
+    ]);
+    assert_eq!(tracker.next_deadline(debounce), None);
+  }
+
+  #[test]
+  fn flapping_peers_are_not_reported() {
+    let config = config(&["nas"]);
+    let debounce = config.debounce;
+    let start = Instant::now();
+    let mut tracker = PeerTracker::default();
+
+    tracker.observe(&with_nas_online(true), &config, start);
+    tracker.observe(&with_nas_online(false), &config, start);
+    tracker.observe(&with_nas_online(true), &config, start + debounce / 2);
+
+    assert!(tracker.settle(debounce, start + debounce * 2).is_empty());
+    assert_eq!(tracker.next_deadline(debounce), None);
+  }
+
+  #[test]
+  fn only_watched_peers_are_tracked() {
+    let start = Instant::now();
+    let mut tracker = PeerTracker::default();
+
+    tracker.observe(
+      &with_nas_online(true),
+      &config(&["build-runner-01"]),
+      start,
+    );
+    tracker.observe(
+      &with_nas_online(false),
+      &config(&["build-runner-01"]),
+      start,
+    );
+    assert_eq!(tracker.next_deadline(Duration::ZERO), None);
+
+    tracker.observe(&with_nas_online(false), &config(&["*"]), start);
+    tracker.observe(&with_nas_online(true), &config(&["*"]), start);
+    assert_eq!(tracker.settle(Duration::ZERO, start).len(), 1);
+  }
+
+  #[test]
+  fn expiry_warnings_escalate_once_per_threshold() {
+    let mut status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let mut config = NotificationConfig::default();
+    let mut tracker = ExpiryTracker::default();
+    let at = |time: &str| time.parse::<Timestamp>().unwrap();
+
+    assert!(
+      tracker
+        .check(&status, &config, at("2025-08-01T00:00:00Z"))
+        .is_empty()
+    );
+    let warnings = tracker.check(&status, &config, at("2025-08-20T00:00:00Z"));
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].this_device);
+    assert!(
+      tracker
+        .check(&status, &config, at("2025-08-21T00:00:00Z"))
+        .is_empty()
+    );
+
+    // Passing several thresholds at once warns only once
+    let expired = at("2025-09-01T00:00:00Z");
+    assert_eq!(tracker.check(&status, &config, expired).len(), 1);
+    assert!(tracker.check(&status, &config, expired).is_empty());
+
+    status.this_machine.key_expiry = Some(at("2026-02-25T00:00:00Z"));
+    assert!(tracker.check(&status, &config, expired).is_empty());
+
+    config.peers.watch = vec!["nas".to_owned()];
+    let warnings = tracker.check(&status, &config, expired);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].name, "nas");
+    assert!(!warnings[0].this_device);
+  }
+
+  #[test]
+  fn expiry_notices_start_at_the_first_threshold() {
+    let config = ExpiryAlertConfig::default();
+    let now = Timestamp::UNIX_EPOCH;
+    let in_days = |days: i64| Some(now + SignedDuration::from_hours(days * 24));
+
+    assert_eq!(config.notice(in_days(15), now), None);
+    assert_eq!(
+      config.notice(in_days(3), now).as_deref(),
+      Some("expires in 3 days")
+    );
+    assert_eq!(
+      config.notice(in_days(-1), now).as_deref(),
+      Some("expired 1 day ago")
+    );
+    assert_eq!(config.notice(None, now), None);
+  }
+
+  #[test]
+  fn health_warnings_are_reported_once_while_present() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let mut tracker = HealthTracker::default();
+
+    assert_eq!(tracker.check(&status), status.health());
+    assert!(tracker.check(&status).is_empty());
+
+    assert!(tracker.check(&Status::default()).is_empty());
+    assert_eq!(tracker.check(&status), status.health());
+  }
+
+  #[test]
+  fn many_changes_are_summarized() {
+    let change = |name: &str, online| {
+      PeerChange {
+        name: name.to_owned(),
+        online,
+      }
+    };
+    let (summary, body, all_online) =
+      summarize(&[change("a", true), change("b", false), change("c", false)]);
+
+    assert_eq!(summary, "3 peers changed state");
+    assert_eq!(body, "Online: a\nOffline: b, c");
+    assert!(!all_online);
+  }
+}
@@ -1,5 +1,14 @@
-use std::{error::Error, fmt};
+use std::{
+  error::Error,
+  fmt,
+  fs,
+  io,
+  path::PathBuf,
+  process::Command,
+  sync::Arc,
+};
 
+use jiff::Timestamp;
 use ksni::{
   self,
   Icon,
@@ -7,26 +16,38 @@ use ksni::{
   OfflineReason,
   ToolTip,
   Tray,
-  menu::{StandardItem, SubMenu},
+  menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu},
 };
 use log::{debug, error, info};
-use notify_rust::Notification;
 
 use crate::{
+  cli::StatusSummary,
+  config::{self, Config, IconMode},
+  dbus,
   elevation::run_with_elevation,
   error::AppError,
-  svg::renderer::{Resvg, Theme},
+  notification::{self, Action, Category},
+  svg::{
+    renderer::{IconState, Resvg},
+    symbolic,
+  },
   tailscale::{
-    peer::copy_peer_ip,
+    exit_node,
+    localapi::LocalApi,
+    peer::{copy_peer_ip, copy_text},
+    prefs::Prefs,
     status::{Status, get_current},
-    utils::PeerKind,
+    utils::Machine,
   },
+  tray::{alerts, peers, tooltip},
 };
 
 /// Custom error type for `SystemTray` operations
 #[derive(Debug)]
 pub enum TrayError {
   Command(String),
+  UnknownExitNode(String),
+  NoExitNodeSuggestion,
   StatusUpdate(String),
   Notification(String),
 }
@@ -35,6 +56,8 @@ impl fmt::Display for TrayError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
       Self::Command(msg) => write!(f, "Command execution failed: {msg}"),
+      Self::UnknownExitNode(name) => write!(f, "No exit node matches '{name}'"),
+      Self::NoExitNodeSuggestion => write!(f, "No exit node was suggested"),
       Self::StatusUpdate(msg) => write!(f, "Status update failed: {msg}"),
       Self::Notification(msg) => write!(f, "Notification failed: {msg}"),
     }
@@ -44,11 +67,12 @@ impl fmt::Display for TrayError {
 impl Error for TrayError {}
 
 /// Represents the context for the system tray
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Context {
   pub ip:     String,
   pub status: Status,
-  pub theme:  Theme,
+  pub prefs:  Prefs,
+  pub config: Arc<Config>,
 }
 
 impl Default for Context {
@@ -56,11 +80,23 @@ impl Default for Context {
     Self {
       ip:     String::default(),
       status: Status::default(),
-      theme:  Theme::from_env(),
+      prefs:  Prefs::default(),
+      config: config::current(),
     }
   }
 }
 
+impl Context {
+  /// Returns whether this context differs from `previous` in a way that
+  /// should be reflected in the tray
+  pub fn has_changed(&self, previous: &Self) -> bool {
+    self.ip != previous.ip
+      || self.prefs != previous.prefs
+      || self.config != previous.config
+      || !self.status.same_state(&previous.status)
+  }
+}
+
 /// The main `SystemTray` implementation
 #[derive(Debug)]
 pub struct SysTray {
@@ -73,11 +109,18 @@ impl SysTray {
     self.ctx.status.tailscale_up
   }
 
+  /// Replaces the context and lets D-Bus clients know about the change
+  pub fn set_context(&mut self, ctx: Context) {
+    self.ctx = ctx;
+    dbus::publish(&self.ctx);
+    alerts::observe(&self.ctx.status);
+  }
+
   /// Updates the Tailscale status
   pub fn update_status(&mut self) -> Result<(), AppError> {
     match get_current() {
       Ok(ctx) => {
-        self.ctx = ctx;
+        self.set_context(ctx);
         Ok(())
       },
       Err(e) => {
@@ -87,54 +130,226 @@ impl SysTray {
     }
   }
 
+  /// Shows the status summary from the menu as a notification
+  ///
+  /// StatusNotifierItem offers no way for an item to open its own menu, so
+  /// this is the closest a command can get to showing it.
+  pub fn show_summary(&self) -> Result<(), AppError> {
+    let summary = StatusSummary::new(&self.ctx.status);
+    notify(
+      Category::Connection,
+      "Tailscale",
+      &summary.to_string(),
+      "tailscale",
+    )
+  }
+
+  /// Connects when disconnected and disconnects otherwise
+  pub fn toggle(&mut self) -> Result<(), AppError> {
+    let verb = if self.enabled() { "down" } else { "up" };
+    self.do_service_link(verb)
+  }
+
   /// Executes a Tailscale service command (up/down)
   pub fn do_service_link(&mut self, verb: &str) -> Result<(), AppError> {
     match run_with_elevation("tailscale", &[verb]) {
       Ok(_) => {
         info!("Link {}: success", verb);
+        self.update_status()?;
 
-        let notify = |summary: &str, body: &str, icon: &str| {
-          Notification::new()
-            .summary(summary)
-            .body(body)
-            .icon(icon)
-            .show()
-            .map_err(|e| {
-              error!("Failed to show notification: {e}");
-              AppError::Tray(TrayError::Notification(e.to_string()))
-            })
+        // The link itself succeeded, so a missing notification is no error;
+        // the helpers already logged it
+        let _ = if verb == "up" {
+          notify(
+            Category::Connection,
+            "Connection up",
+            "Tailscale service online",
+            "info",
+          )
+        } else {
+          notify_with_actions(
+            Category::Connection,
+            "Connection down",
+            "Tailscale service offline",
+            "info",
+            vec![(Action::ServiceLink("up".into()), "Reconnect")],
+          )
         };
 
-        notify(
-          &format!("Connection {verb}"),
-          &format!(
-            "Tailscale service {}",
-            if verb == "up" { "online" } else { "offline" }
-          ),
-          "info",
-        )?;
-        self.update_status()?;
-
         Ok(())
       },
       Err(e) => {
         error!("Failed to execute command: {e}");
 
-        let notify = |summary: &str, body: &str, icon: &str| {
-          Notification::new()
-            .summary(summary)
-            .body(body)
-            .icon(icon)
-            .show()
-            .map_err(|e| {
-              error!("Failed to show notification: {e}");
-              AppError::Tray(TrayError::Notification(e.to_string()))
-            })
-        };
+        let message = format!("Failed to {verb} Tailscale: {e}");
+        notify_with_actions(
+          Category::Connection,
+          "Connection Failed",
+          &message,
+          "error",
+          vec![
+            (Action::ServiceLink(verb.to_owned()), "Retry"),
+            (Action::ShowLog(message.clone()), "Show Log"),
+          ],
+        )?;
+
+        Err(AppError::Tray(TrayError::Command(e.to_string())))
+      },
+    }
+  }
 
+  /// Starts an interactive login to renew this device's key and opens the
+  /// login page in the browser
+  pub fn reauthenticate(&mut self) -> Result<(), AppError> {
+    let url = LocalApi::default().login_interactive().map_err(|e| {
+      error!("Failed to start re-authentication: {e}");
+      let _ = notify(
+        Category::Expiry,
+        "Re-authentication failed",
+        &e.to_string(),
+        "error",
+      );
+      AppError::Tray(TrayError::Command(e.to_string()))
+    })?;
+
+    info!("Opening login page {url}");
+    if let Err(e) = open::that(&url) {
+      error!("Failed to open {url}: {e}");
+      notify(
+        Category::Expiry,
+        "Re-authenticate Tailscale",
+        &format!("Visit {url} to log in."),
+        "dialog-information",
+      )?;
+    }
+    Ok(())
+  }
+
+  /// Writes `error` and tailscaled's recent log to a file and opens it
+  pub fn show_log(&mut self, error: &str) -> Result<(), AppError> {
+    let path = write_log(error).map_err(|e| {
+      error!("Failed to write the log: {e}");
+      AppError::Tray(TrayError::Command(e.to_string()))
+    })?;
+
+    open::that(&path).map_err(|e| {
+      error!("Failed to open {}: {e}", path.display());
+      AppError::Tray(TrayError::Command(e.to_string()))
+    })
+  }
+
+  /// Routes traffic through the exit node at `target`, or stops using an
+  /// exit node when `target` is empty
+  pub fn set_exit_node(
+    &mut self,
+    target: &str,
+    name: &str,
+  ) -> Result<(), AppError> {
+    let body = if target.is_empty() {
+      "No longer using an exit node".to_owned()
+    } else {
+      format!("Routing traffic through {name}")
+    };
+
+    self.tailscale_set(
+      &format!("--exit-node={target}"),
+      "Exit node changed",
+      &body,
+      "Exit Node Failed",
+    )
+  }
+
+  /// Routes traffic through the exit node whose name or address matches
+  /// `query`, or stops using one when `query` is empty or "none"
+  pub fn set_exit_node_by_name(&mut self, query: &str) -> Result<(), AppError> {
+    if query.is_empty() || query.eq_ignore_ascii_case("none") {
+      return self.set_exit_node("", "");
+    }
+
+    let (target, name) = self
+      .ctx
+      .status
+      .find_peer(query)
+      .filter(|peer| peer.exit_node_option && !peer.ips.is_empty())
+      .map(|peer| (peer.ips[0].to_string(), peer.display_name.to_string()))
+      .ok_or_else(|| TrayError::UnknownExitNode(query.to_owned()))?;
+    self.set_exit_node(&target, &name)
+  }
+
+  /// Switches to the exit node suggested by tailscaled
+  pub fn use_suggested_exit_node(&mut self) -> Result<(), AppError> {
+    let suggestion = match LocalApi::default().suggest_exit_node() {
+      Ok(suggestion) => suggestion,
+      Err(e) => {
+        error!("Failed to get exit node suggestion: {e}");
         notify(
-          "Connection Failed",
-          &format!("Failed to {verb} Tailscale: {e}"),
+          Category::Connection,
+          "Exit Node Failed",
+          &format!("Failed to get a suggested exit node: {e}"),
+          "error",
+        )?;
+        return Err(e.into());
+      },
+    };
+
+    // An empty target would stop using an exit node instead
+    let target = suggestion.target(&self.ctx.status);
+    if target.is_empty() {
+      let e = TrayError::NoExitNodeSuggestion;
+      error!("{e}");
+      notify(
+        Category::Connection,
+        "Exit Node Failed",
+        "tailscaled has no exit node to suggest",
+        "error",
+      )?;
+      return Err(e.into());
+    }
+
+    let name = suggestion.name.trim_end_matches('.').to_owned();
+    self.set_exit_node(&target, &name)
+  }
+
+  /// Allows or denies access to the local network while using an exit node
+  pub fn set_exit_node_lan_access(
+    &mut self,
+    allow: bool,
+  ) -> Result<(), AppError> {
+    let body = if allow {
+      "Local network is reachable while using an exit node"
+    } else {
+      "Local network is blocked while using an exit node"
+    };
+
+    self.tailscale_set(
+      &format!("--exit-node-allow-lan-access={allow}"),
+      "LAN access changed",
+      body,
+      "LAN Access Failed",
+    )
+  }
+
+  /// Applies a single `tailscale set` flag and notifies about the outcome,
+  /// titling a failure with `failure_summary`
+  fn tailscale_set(
+    &mut self,
+    flag: &str,
+    summary: &str,
+    body: &str,
+    failure_summary: &str,
+  ) -> Result<(), AppError> {
+    match run_with_elevation("tailscale", &["set", flag]) {
+      Ok(_) => {
+        info!("tailscale set {flag}: success");
+        notify(Category::Connection, summary, body, "info")?;
+        self.update_status()
+      },
+      Err(e) => {
+        error!("Failed to run tailscale set {flag}: {e}");
+        notify(
+          Category::Connection,
+          failure_summary,
+          &format!("Failed to apply {flag}: {e}"),
           "error",
         )?;
 
@@ -142,15 +357,223 @@ impl SysTray {
       },
     }
   }
+
+  /// Builds the entries for a group of peers, honoring the configured order
+  /// and handling of offline peers
+  fn peer_items(&self, peers: Vec<&Machine>) -> Vec<MenuItem<Self>> {
+    let (listed, offline) = self.ctx.config.menu.arrange(peers);
+    let config = &self.ctx.config.menu;
+    let now = Timestamp::now();
+    let item = |peer: &Machine| {
+      let expiry = &self.ctx.config.notifications.expiry;
+      peers::peer_item(peer, config, expiry.notice(peer.key_expiry, now))
+    };
+    let mut items: Vec<_> = listed.into_iter().map(item).collect();
+
+    if !offline.is_empty() {
+      items.push(MenuItem::Separator);
+      items.push(
+        SubMenu {
+          label: format!("Offline ({})", offline.len()),
+          submenu: offline.into_iter().map(item).collect(),
+          ..Default::default()
+        }
+        .into(),
+      );
+    }
+
+    items
+  }
+
+  /// Builds the submenu listing tailscaled's health warnings, hidden while
+  /// there are none
+  fn health_menu(&self) -> MenuItem<Self> {
+    let warnings = self.ctx.status.health();
+    SubMenu {
+      label: format!("Health ({})", warnings.len()),
+      icon_name: "dialog-warning-symbolic".into(),
+      visible: !warnings.is_empty(),
+      submenu: warnings
+        .iter()
+        .map(|warning| {
+          let warning = warning.clone();
+          StandardItem {
+            label: warning.clone(),
+            activate: Box::new(move |_| {
+              if let Err(e) =
+                copy_text(&warning, "Copied health warning", &warning)
+              {
+                error!("Failed to copy health warning: {e}");
+              }
+            }),
+            ..Default::default()
+          }
+          .into()
+        })
+        .collect(),
+      ..Default::default()
+    }
+    .into()
+  }
+
+  /// Builds the exit node submenu from peers advertising themselves as exit
+  /// nodes
+  fn exit_node_menu(&self) -> MenuItem<Self> {
+    let options = exit_node::options(&self.ctx.status);
+    let selected = options
+      .iter()
+      .position(|peer| peer.exit_node)
+      .map_or(0, |index| index + 1);
+
+    // Index 0 is the "None" entry, the rest line up with `options`
+    let targets: Vec<(String, String)> =
+      std::iter::once((String::new(), String::new()))
+        .chain(
+          options.iter().map(|peer| {
+            (peer.ips[0].to_string(), peer.display_name.to_string())
+          }),
+        )
+        .collect();
+
+    let radio_items = std::iter::once(RadioItem {
+      label: "None".into(),
+      ..Default::default()
+    })
+    .chain(options.iter().map(|peer| {
+      RadioItem {
+        label: peer.display_name.to_string(),
+        ..Default::default()
+      }
+    }))
+    .collect();
+
+    SubMenu {
+      label: "Exit Node".into(),
+      icon_name: "network-vpn-symbolic".into(),
+      enabled: self.enabled(),
+      submenu: vec![
+        RadioGroup {
+          selected,
+          select: Box::new(move |this: &mut Self, index| {
+            let Some((target, name)) = targets.get(index) else {
+              return;
+            };
+            if let Err(e) = this.set_exit_node(target, name) {
+              error!("Failed to set exit node: {e}");
+            }
+          }),
+          options: radio_items,
+        }
+        .into(),
+        MenuItem::Separator,
+        StandardItem {
+          label: "Suggested".into(),
+          icon_name: "starred-symbolic".into(),
+          activate: Box::new(|this: &mut Self| {
+            if let Err(e) = this.use_suggested_exit_node() {
+              error!("Failed to use suggested exit node: {e}");
+            }
+          }),
+          ..Default::default()
+        }
+        .into(),
+        CheckmarkItem {
+          label: "Allow LAN access".into(),
+          checked: self.ctx.prefs.exit_node_allow_lan_access,
+          activate: Box::new(|this: &mut Self| {
+            let allow = !this.ctx.prefs.exit_node_allow_lan_access;
+            if let Err(e) = this.set_exit_node_lan_access(allow) {
+              error!("Failed to toggle LAN access: {e}");
+            }
+          }),
+          ..Default::default()
+        }
+        .into(),
+      ],
+      ..Default::default()
+    }
+    .into()
+  }
+}
+
+/// Shows a desktop notification
+fn notify(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+) -> Result<(), AppError> {
+  notification::show(category, summary, body, icon).map_err(|e| {
+    error!("Failed to show notification: {e}");
+    AppError::Tray(TrayError::Notification(e.to_string()))
+  })
+}
+
+/// Shows a desktop notification with buttons routed back to the tray
+fn notify_with_actions(
+  category: Category,
+  summary: &str,
+  body: &str,
+  icon: &str,
+  actions: Vec<(Action, &str)>,
+) -> Result<(), AppError> {
+  notification::show_with_actions(category, summary, body, icon, actions)
+    .map_err(|e| {
+      error!("Failed to show notification: {e}");
+      AppError::Tray(TrayError::Notification(e.to_string()))
+    })
+}
+
+/// Saves `error` followed by the last lines of tailscaled's journal to the
+/// state directory, returning the file's path
+fn write_log(error: &str) -> io::Result<PathBuf> {
+  let directory = config::state_home()
+    .ok_or_else(|| io::Error::other("no state directory could be determined"))?
+    .join("tailray");
+  fs::create_dir_all(&directory)?;
+
+  let journal = Command::new("journalctl")
+    .args(["--unit=tailscaled", "--lines=200", "--no-pager"])
+    .output()
+    .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    .unwrap_or_else(|e| format!("journalctl is not available: {e}\n"));
+
+  let path = directory.join("last-error.log");
+  fs::write(
+    &path,
+    format!("{error}\n\nRecent tailscaled log:\n{journal}"),
+  )?;
+  Ok(path)
 }
 
 impl Tray for SysTray {
+  fn icon_pixmap(&self) -> Vec<Icon> {
+    Resvg::load_icon(self.ctx.config.theme, IconState::new(&self.ctx.status))
+  }
+
   fn icon_name(&self) -> String {
-    String::new()
+    let icons = &self.ctx.config.icons;
+    match icons.mode {
+      IconMode::Pixmap => String::new(),
+      IconMode::Name => icons.name(IconState::new(&self.ctx.status)),
+    }
   }
 
-  fn icon_pixmap(&self) -> Vec<Icon> {
-    Resvg::load_icon(self.ctx.theme, self.enabled())
+  fn icon_theme_path(&self) -> String {
+    let icons = &self.ctx.config.icons;
+    match icons.mode {
+      IconMode::Pixmap => String::new(),
+      IconMode::Name => {
+        match &icons.theme_path {
+          Some(path) => path.display().to_string(),
+          None => {
+            symbolic::theme_path()
+              .map(|path| path.display().to_string())
+              .unwrap_or_default()
+          },
+        }
+      },
+    }
   }
 
   fn id(&self) -> String {
@@ -162,15 +585,14 @@ impl Tray for SysTray {
   }
 
   fn tool_tip(&self) -> ToolTip {
-    let state = if self.enabled() {
-      "Connected"
-    } else {
-      "Disconnected"
-    };
-
+    let status = &self.ctx.status;
     ToolTip {
-      title:       format!("Tailscale: {state}"),
-      description: String::default(),
+      title:       format!("Tailscale: {}", tooltip::state_label(status)),
+      description: tooltip::render(
+        &self.ctx.config.tooltip.template,
+        status,
+        Timestamp::now(),
+      ),
       icon_name:   String::default(),
       icon_pixmap: Vec::default(),
     }
@@ -184,42 +606,26 @@ impl Tray for SysTray {
     debug!("Creating menu with device {message}");
 
     // Prepare device submenus
-    let (my_sub, serv_sub): (Vec<_>, Vec<_>) = self
-      .ctx
-      .status
-      .peers
-      .iter()
-      .filter(|(_, peer)| !peer.ips.is_empty())
-      .map(|(_, peer)| {
-        let ip = peer.ips[0].clone();
-        let name = &peer.display_name;
-        let peer_title = format!("{name} ({ip})");
-        let menu = MenuItem::Standard(StandardItem {
-          label: format!("{name}\t({ip})"),
-          activate: Box::new(move |_: &mut Self| {
-            if let Err(e) = copy_peer_ip(&ip, &peer_title, false) {
-              error!("Failed to copy peer IP: {e}");
-            }
-          }),
-          ..Default::default()
-        });
-        match name {
-          PeerKind::DNSName(_) => (None, Some(menu)),
-          PeerKind::HostName(_) => (Some(menu), None),
-        }
-      })
-      .fold(
-        (Vec::new(), Vec::new()),
-        |(mut my, mut serv), (my_item, serv_item)| {
-          if let Some(item) = my_item {
-            my.push(item);
-          }
-          if let Some(item) = serv_item {
-            serv.push(item);
-          }
-          (my, serv)
+    let devices: Vec<MenuItem<Self>> =
+      match peers::group_peers(&self.ctx.status, self.ctx.config.menu.grouping)
+        .as_slice()
+      {
+        [] => Vec::new(),
+        [(name, peers)] if name.is_empty() => self.peer_items(peers.clone()),
+        groups => {
+          groups
+            .iter()
+            .map(|(name, peers)| {
+              SubMenu {
+                label: name.clone(),
+                submenu: self.peer_items(peers.clone()),
+                ..Default::default()
+              }
+              .into()
+            })
+            .collect()
         },
-      );
+      };
 
     vec![
       StandardItem {
@@ -260,42 +666,36 @@ impl Tray for SysTray {
         ..Default::default()
       }
       .into(),
+      self.health_menu(),
       SubMenu {
         label: "Network Devices".into(),
         icon_name: "network-wired-symbolic".into(),
-        submenu: vec![
-          SubMenu {
-            label: "My Devices".into(),
-            submenu: my_sub,
-            ..Default::default()
-          }
-          .into(),
-          SubMenu {
-            label: "Tailscale Services".into(),
-            submenu: serv_sub,
-            ..Default::default()
-          }
-          .into(),
-        ],
+        submenu: devices,
         ..Default::default()
       }
       .into(),
+      self.exit_node_menu(),
       StandardItem {
         label: "Admin Console".into(),
         icon_name: "applications-system-symbolic".into(),
-        activate: Box::new(|_| {
-          let admin_url =
-            std::env::var("TAILRAY_ADMIN_URL").unwrap_or_else(|_| {
-              "https://login.tailscale.com/admin/machines".to_string()
-            });
-
-          if let Err(e) = open::that(&admin_url) {
+        activate: Box::new(|this: &mut Self| {
+          if let Err(e) = open::that(&this.ctx.config.admin_url) {
             error!("Failed to open admin console: {e}");
           }
         }),
         ..Default::default()
       }
       .into(),
+      CheckmarkItem {
+        label: "Do Not Disturb".into(),
+        icon_name: "notifications-disabled-symbolic".into(),
+        checked: notification::do_not_disturb(),
+        activate: Box::new(|_| {
+          notification::set_do_not_disturb(!notification::do_not_disturb());
+        }),
+        ..Default::default()
+      }
+      .into(),
       MenuItem::Separator,
       StandardItem {
         label: "Exit Tailray".into(),
@@ -322,3 +722,44 @@ impl Tray for SysTray {
     false
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("../tailscale/testdata/status.json");
+
+  fn context() -> Context {
+    Context {
+      status: status::parse(STATUS_JSON).expect("fixture should parse"),
+      ..Context::default()
+    }
+  }
+
+  #[test]
+  fn only_visible_changes_count() {
+    let previous = context();
+    assert!(!context().has_changed(&previous));
+
+    let mut traffic = context();
+    for peer in traffic.status.peers.values_mut() {
+      peer.rx_bytes += 1;
+    }
+    assert!(!traffic.has_changed(&previous));
+
+    let mut ip = context();
+    ip.ip = "100.64.0.1".into();
+    assert!(ip.has_changed(&previous));
+
+    let mut prefs = context();
+    prefs.prefs.exit_node_allow_lan_access = true;
+    assert!(prefs.has_changed(&previous));
+
+    let mut offline = context();
+    for peer in offline.status.peers.values_mut() {
+      peer.online = false;
+    }
+    assert!(offline.has_changed(&previous));
+  }
+}
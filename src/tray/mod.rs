@@ -1,2 +1,5 @@
+pub mod alerts;
 pub mod menu;
+pub mod peers;
+pub mod tooltip;
 pub mod utils;
@@ -0,0 +1,593 @@
+use std::{cmp::Reverse, collections::BTreeMap, net::IpAddr, thread};
+
+use ksni::{
+  MenuItem,
+  menu::{StandardItem, SubMenu},
+};
+use log::error;
+use serde::Deserialize;
+
+use crate::{
+  tailscale::{
+    peer::{self, copy_peer_ip, copy_text},
+    status::Status,
+    utils::{Machine, Presence},
+  },
+  tray::menu::SysTray,
+};
+
+const MY_DEVICES: &str = "My Devices";
+const OTHER_USERS: &str = "Other Users";
+const TAILSCALE_SERVICES: &str = "Tailscale Services";
+const SHARED_WITH_ME: &str = "Shared With Me";
+
+/// Ownership groups in the order they are shown
+const OWNERSHIP_GROUPS: [&str; 4] =
+  [MY_DEVICES, OTHER_USERS, TAILSCALE_SERVICES, SHARED_WITH_ME];
+
+/// Callback run when a menu entry is activated
+type Activate = Box<dyn Fn(&mut SysTray) + Send>;
+
+/// Order of peers within the device menus
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerSort {
+  /// Online peers first, then idle, then offline, each alphabetically
+  #[default]
+  Status,
+  /// Alphabetically by name
+  Name,
+  /// Most recently seen first, with online peers on top
+  LastSeen,
+}
+
+/// How peers that are offline are presented
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OfflinePeers {
+  /// List offline peers alongside online ones
+  #[default]
+  Show,
+  /// Leave offline peers out entirely
+  Hide,
+  /// Move offline peers into an "Offline" submenu
+  Collapse,
+}
+
+/// How peers are split into submenus of "Network Devices"
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerGrouping {
+  /// My devices, other users' devices, tagged services and shared-in nodes
+  #[default]
+  Ownership,
+  /// By operating system
+  Os,
+  /// By ACL tag, listing a peer under each of its tags
+  Tag,
+  /// By owning user
+  User,
+  /// No submenus at all
+  Flat,
+}
+
+/// Actions offered in the submenu of every peer
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerAction {
+  CopyIpv4,
+  CopyIpv6,
+  CopyDnsName,
+  CopySsh,
+  OpenBrowser,
+  Ping,
+  Taildrop,
+  Details,
+}
+
+impl PeerAction {
+  /// Every action, in menu order
+  pub const ALL: [Self; 8] = [
+    Self::CopyIpv4,
+    Self::CopyIpv6,
+    Self::CopyDnsName,
+    Self::CopySsh,
+    Self::OpenBrowser,
+    Self::Ping,
+    Self::Taildrop,
+    Self::Details,
+  ];
+}
+
+/// Presentation settings for the device menus
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerMenuConfig {
+  pub grouping:       PeerGrouping,
+  pub sort:           PeerSort,
+  #[serde(rename = "offline_peers")]
+  pub offline:        OfflinePeers,
+  #[serde(rename = "hidden_peer_actions")]
+  pub hidden_actions: Vec<PeerAction>,
+}
+
+impl PeerMenuConfig {
+  /// Returns whether `action` should be offered for peers
+  pub fn shows(&self, action: PeerAction) -> bool {
+    !self.hidden_actions.contains(&action)
+  }
+
+  /// Sorts `peers` and splits off the ones that belong in an "Offline"
+  /// submenu, dropping offline peers entirely if they are hidden
+  pub fn arrange<'a>(
+    &self,
+    mut peers: Vec<&'a Machine>,
+  ) -> (Vec<&'a Machine>, Vec<&'a Machine>) {
+    sort_peers(&mut peers, self.sort);
+
+    match self.offline {
+      OfflinePeers::Show => (peers, Vec::new()),
+      OfflinePeers::Hide => {
+        peers.retain(|peer| peer.presence() != Presence::Offline);
+        (peers, Vec::new())
+      },
+      OfflinePeers::Collapse => {
+        peers
+          .into_iter()
+          .partition(|peer| peer.presence() != Presence::Offline)
+      },
+    }
+  }
+}
+
+/// Splits the reachable peers of `status` into named groups according to
+/// `grouping`
+///
+/// Groups come back in display order and empty groups are left out. With
+/// [`PeerGrouping::Flat`] all peers end up in a single unnamed group.
+pub fn group_peers(
+  status: &Status,
+  grouping: PeerGrouping,
+) -> Vec<(String, Vec<&Machine>)> {
+  // Like `tailscale status`, leave out nodes that are only in the netmap
+  // because their owner was shared one of ours
+  let peers = status
+    .peers
+    .values()
+    .filter(|peer| !peer.ips.is_empty() && !peer.sharee_node);
+
+  match grouping {
+    PeerGrouping::Flat => vec![(String::new(), peers.collect())],
+    PeerGrouping::Ownership => {
+      OWNERSHIP_GROUPS
+        .into_iter()
+        .map(|name| {
+          let members = peers
+            .clone()
+            .filter(|peer| ownership_group(status, peer) == name)
+            .collect();
+          (name.to_owned(), members)
+        })
+        .filter(|(_, members): &(String, Vec<_>)| !members.is_empty())
+        .collect()
+    },
+    PeerGrouping::Os => {
+      named_groups(peers, "Unknown", |peer| {
+        (!peer.os.is_empty()).then(|| vec![peer.os.clone()])
+      })
+    },
+    PeerGrouping::Tag => {
+      named_groups(peers, "Untagged", |peer| {
+        (!peer.tags.is_empty()).then(|| {
+          peer
+            .tags
+            .iter()
+            .map(|tag| tag.trim_start_matches("tag:").to_owned())
+            .collect()
+        })
+      })
+    },
+    PeerGrouping::User => {
+      named_groups(peers, "Unknown User", |peer| {
+        status
+          .user_name(peer.user_id)
+          .map(|name| vec![name.to_owned()])
+      })
+    },
+  }
+}
+
+/// Returns the name of the ownership group `peer` belongs to
+fn ownership_group(status: &Status, peer: &Machine) -> &'static str {
+  if is_shared_in(status, peer) {
+    SHARED_WITH_ME
+  } else if !peer.tags.is_empty() {
+    TAILSCALE_SERVICES
+  } else if peer.user_id == status.this_machine.user_id {
+    MY_DEVICES
+  } else {
+    OTHER_USERS
+  }
+}
+
+/// Returns whether `peer` was shared into this tailnet from another one,
+/// which shows in an owner this tailnet does not know or in the MagicDNS
+/// suffix of its home tailnet
+fn is_shared_in(status: &Status, peer: &Machine) -> bool {
+  let suffix = status.magic_dns_suffix();
+  let dns_name = peer.dns_name.trim_end_matches('.');
+
+  status.user_name(peer.user_id).is_none()
+    || (!suffix.is_empty()
+      && !dns_name.is_empty()
+      && !dns_name.ends_with(&format!(".{suffix}")))
+}
+
+/// Groups `peers` by the names returned from `names`, sorted alphabetically,
+/// with peers that have no name collected under `fallback` at the end
+fn named_groups<'a>(
+  peers: impl Iterator<Item = &'a Machine>,
+  fallback: &str,
+  names: impl Fn(&Machine) -> Option<Vec<String>>,
+) -> Vec<(String, Vec<&'a Machine>)> {
+  let mut groups: BTreeMap<String, Vec<&Machine>> = BTreeMap::new();
+  let mut rest = Vec::new();
+
+  for peer in peers {
+    match names(peer) {
+      Some(names) => {
+        for name in names {
+          groups.entry(name).or_default().push(peer);
+        }
+      },
+      None => rest.push(peer),
+    }
+  }
+
+  let mut groups: Vec<_> = groups.into_iter().collect();
+  if !rest.is_empty() {
+    groups.push((fallback.to_owned(), rest));
+  }
+  groups
+}
+
+/// Sorts `peers` in place according to `sort`, breaking ties by name
+pub fn sort_peers(peers: &mut [&Machine], sort: PeerSort) {
+  match sort {
+    PeerSort::Status => {
+      peers.sort_by_cached_key(|peer| (peer.presence(), sort_name(peer)));
+    },
+    PeerSort::Name => peers.sort_by_cached_key(|peer| sort_name(peer)),
+    PeerSort::LastSeen => {
+      peers.sort_by_cached_key(|peer| {
+        (!peer.online, Reverse(peer.last_seen), sort_name(peer))
+      });
+    },
+  }
+}
+
+/// Returns the menu label for `peer`, prefixed with its presence marker and
+/// flagged when its key is about to expire
+pub fn label(peer: &Machine, ip: &str, expiring: bool) -> String {
+  let flag = if expiring { " ⚠" } else { "" };
+  format!(
+    "{} {}{flag}\t({ip})",
+    peer.presence().marker(),
+    peer.display_name
+  )
+}
+
+/// Builds the submenu for a single peer with all actions not hidden by
+/// `config`, headed by `expiry_notice` when its key is about to expire
+pub fn peer_item(
+  peer: &Machine,
+  config: &PeerMenuConfig,
+  expiry_notice: Option<String>,
+) -> MenuItem<SysTray> {
+  let ip = peer.ips[0].to_string();
+  let mut submenu = Vec::new();
+  if let Some(notice) = &expiry_notice {
+    submenu.push(
+      StandardItem {
+        label: format!("Key {notice}"),
+        icon_name: "dialog-warning-symbolic".into(),
+        enabled: false,
+        ..Default::default()
+      }
+      .into(),
+    );
+    submenu.push(MenuItem::Separator);
+  }
+  submenu.extend(
+    PeerAction::ALL
+      .into_iter()
+      .filter(|action| config.shows(*action))
+      .filter_map(|action| action_item(peer, action)),
+  );
+
+  SubMenu {
+    label: label(peer, &ip, expiry_notice.is_some()),
+    submenu,
+    ..Default::default()
+  }
+  .into()
+}
+
+/// Builds the entry for `action` on `peer`, or `None` if the action does not
+/// apply to it
+fn action_item(
+  peer: &Machine,
+  action: PeerAction,
+) -> Option<MenuItem<SysTray>> {
+  let name = peer.display_name.to_string();
+  let address = peer.address();
+
+  let (label, icon_name, activate): (String, &str, Activate) = match action {
+    PeerAction::CopyIpv4 | PeerAction::CopyIpv6 => {
+      let want_v4 = action == PeerAction::CopyIpv4;
+      let ip = peer
+        .ips
+        .iter()
+        .find(|ip| matches!(ip, IpAddr::V4(_)) == want_v4)?
+        .to_string();
+      let family = if want_v4 { "IPv4" } else { "IPv6" };
+      let body = format!("{name} ({ip})");
+
+      (
+        format!("Copy {family}\t{ip}"),
+        "edit-copy-symbolic",
+        Box::new(move |_| {
+          if let Err(e) = copy_peer_ip(&ip, &body, false) {
+            error!("Failed to copy peer {family} address: {e}");
+          }
+        }),
+      )
+    },
+    PeerAction::CopyDnsName => {
+      let dns_name = peer.magic_dns_name()?.to_owned();
+
+      (
+        "Copy MagicDNS name".into(),
+        "edit-copy-symbolic",
+        Box::new(move |_| {
+          if let Err(e) =
+            copy_text(&dns_name, "Copied MagicDNS name", &dns_name)
+          {
+            error!("Failed to copy MagicDNS name: {e}");
+          }
+        }),
+      )
+    },
+    PeerAction::CopySsh => {
+      let user = whoami::username().unwrap_or_else(|_| "root".into());
+      let command = format!("ssh {user}@{address}");
+
+      (
+        "Copy SSH command".into(),
+        "utilities-terminal-symbolic",
+        Box::new(move |_| {
+          if let Err(e) = copy_text(&command, "Copied SSH command", &command) {
+            error!("Failed to copy SSH command: {e}");
+          }
+        }),
+      )
+    },
+    PeerAction::OpenBrowser => {
+      let url = format!("http://{address}");
+
+      (
+        "Open in browser".into(),
+        "web-browser-symbolic",
+        Box::new(move |_| {
+          if let Err(e) = open::that(&url) {
+            error!("Failed to open {url}: {e}");
+          }
+        }),
+      )
+    },
+    PeerAction::Ping => {
+      let peer = peer.clone();
+
+      (
+        "Ping".into(),
+        "network-transmit-receive-symbolic",
+        Box::new(move |_| {
+          let peer = peer.clone();
+          thread::spawn(move || {
+            if let Err(e) = peer::ping(&peer) {
+              error!("Failed to ping peer: {e}");
+            }
+          });
+        }),
+      )
+    },
+    PeerAction::Taildrop => {
+      let peer = peer.clone();
+
+      (
+        "Send file…".into(),
+        "document-send-symbolic",
+        Box::new(move |_| {
+          let peer = peer.clone();
+          thread::spawn(move || {
+            if let Err(e) = peer::send_file(&peer) {
+              error!("Failed to send file via Taildrop: {e}");
+            }
+          });
+        }),
+      )
+    },
+    PeerAction::Details => {
+      let peer = peer.clone();
+
+      (
+        "Show details".into(),
+        "dialog-information-symbolic",
+        Box::new(move |_| {
+          if let Err(e) = peer::show_details(&peer) {
+            error!("Failed to show peer details: {e}");
+          }
+        }),
+      )
+    },
+  };
+
+  Some(
+    StandardItem {
+      label,
+      icon_name: icon_name.into(),
+      enabled: action != PeerAction::Taildrop || peer.online,
+      activate,
+      ..Default::default()
+    }
+    .into(),
+  )
+}
+
+/// Case-insensitive sort key for a peer's name
+fn sort_name(peer: &Machine) -> String {
+  peer.display_name.to_string().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("../tailscale/testdata/status.json");
+
+  fn hostnames(peers: &[&Machine]) -> Vec<String> {
+    peers.iter().map(|peer| peer.hostname.clone()).collect()
+  }
+
+  #[test]
+  fn peers_are_sorted_by_each_order() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+
+    for (sort, expected) in [
+      (PeerSort::Status, [
+        "nas",
+        "bobs-macbook",
+        "build-runner-01",
+        "carols-phone",
+        "printer-gw",
+      ]),
+      (PeerSort::Name, [
+        "bobs-macbook",
+        "build-runner-01",
+        "carols-phone",
+        "nas",
+        "printer-gw",
+      ]),
+      (PeerSort::LastSeen, [
+        "nas",
+        "bobs-macbook",
+        "carols-phone",
+        "build-runner-01",
+        "printer-gw",
+      ]),
+    ] {
+      let mut peers: Vec<_> = status.peers.values().collect();
+      sort_peers(&mut peers, sort);
+      assert_eq!(hostnames(&peers), expected, "{sort:?}");
+    }
+  }
+
+  #[test]
+  fn offline_peers_are_shown_hidden_or_collapsed() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let arrange = |offline| {
+      let config = PeerMenuConfig {
+        offline,
+        ..PeerMenuConfig::default()
+      };
+      let (listed, collapsed) = config.arrange(status.peers.values().collect());
+      (hostnames(&listed), hostnames(&collapsed))
+    };
+
+    let (listed, collapsed) = arrange(OfflinePeers::Show);
+    assert_eq!(listed, [
+      "nas",
+      "bobs-macbook",
+      "build-runner-01",
+      "carols-phone",
+      "printer-gw"
+    ]);
+    assert!(collapsed.is_empty());
+
+    let (listed, collapsed) = arrange(OfflinePeers::Hide);
+    assert_eq!(listed, ["nas", "bobs-macbook"]);
+    assert!(collapsed.is_empty());
+
+    let (listed, collapsed) = arrange(OfflinePeers::Collapse);
+    assert_eq!(listed, ["nas", "bobs-macbook"]);
+    assert_eq!(collapsed, ["build-runner-01", "carols-phone", "printer-gw"]);
+  }
+
+  #[test]
+  fn peers_are_grouped_by_each_mode() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let groups = |grouping| {
+      group_peers(&status, grouping)
+        .into_iter()
+        .map(|(name, peers)| {
+          let mut hosts = hostnames(&peers);
+          hosts.sort();
+          (name, hosts)
+        })
+        .collect::<Vec<_>>()
+    };
+    let group = |name: &str, hosts: &[&str]| {
+      let hosts = hosts.iter().map(ToString::to_string).collect();
+      (name.to_owned(), hosts)
+    };
+
+    assert_eq!(groups(PeerGrouping::Ownership), [
+      group(MY_DEVICES, &["nas"]),
+      group(TAILSCALE_SERVICES, &["build-runner-01", "printer-gw"]),
+      group(SHARED_WITH_ME, &["bobs-macbook"]),
+    ]);
+    assert_eq!(groups(PeerGrouping::Os), [
+      group("linux", &["build-runner-01", "nas", "printer-gw"]),
+      group("macOS", &["bobs-macbook"]),
+    ]);
+    assert_eq!(groups(PeerGrouping::Tag), [
+      group("ci", &["build-runner-01"]),
+      group("iot", &["printer-gw"]),
+      group("server", &["build-runner-01"]),
+      group("Untagged", &["bobs-macbook", "nas"]),
+    ]);
+    assert_eq!(groups(PeerGrouping::User), [
+      group("Alice", &["nas"]),
+      group("Bob", &["bobs-macbook"]),
+      group("tagged-devices", &["build-runner-01", "printer-gw"]),
+    ]);
+    assert_eq!(groups(PeerGrouping::Flat), [group("", &[
+      "bobs-macbook",
+      "build-runner-01",
+      "nas",
+      "printer-gw",
+    ])]);
+  }
+
+  #[test]
+  fn shared_in_peers_are_told_apart_by_owner_or_tailnet() {
+    let mut status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let key = status
+      .peers
+      .iter()
+      .find(|(_, peer)| peer.hostname == "bobs-macbook")
+      .map(|(key, _)| key.clone())
+      .unwrap();
+    let group_of =
+      |status: &Status| ownership_group(status, &status.peers[&key]);
+    assert_eq!(group_of(&status), SHARED_WITH_ME, "foreign tailnet");
+
+    let macbook = status.peers.get_mut(&key).unwrap();
+    macbook.dns_name = "bobs-macbook.tail2f4d1.ts.net.".into();
+    assert_eq!(group_of(&status), OTHER_USERS, "known owner, same tailnet");
+
+    status.peers.get_mut(&key).unwrap().user_id = 42;
+    assert_eq!(group_of(&status), SHARED_WITH_ME, "unknown owner");
+  }
+}
@@ -0,0 +1,272 @@
+use jiff::{SignedDuration, Timestamp};
+
+use crate::tailscale::status::Status;
+
+/// Tooltip description used unless the configuration sets another template
+pub const DEFAULT_TEMPLATE: &str = "Tailnet: {tailnet}
+Device: {dns_name}
+IPv4: {ipv4}
+IPv6: {ipv6}
+Exit node: {exit_node}
+Peers: {peers_online} of {peers_total} online
+User: {user}
+Key expiry: {key_expiry}
+{health}";
+
+/// Placeholders a template may use
+const PLACEHOLDERS: [&str; 12] = [
+  "state",
+  "tailnet",
+  "device",
+  "dns_name",
+  "ipv4",
+  "ipv6",
+  "exit_node",
+  "peers_online",
+  "peers_total",
+  "user",
+  "key_expiry",
+  "health",
+];
+
+/// A part of a template line
+#[derive(Debug, PartialEq, Eq)]
+enum Piece<'a> {
+  Text(&'a str),
+  Placeholder(&'a str),
+}
+
+/// Returns a short description of the connection state
+pub fn state_label(status: &Status) -> &'static str {
+  match status.backend_state() {
+    "Running" => "Connected",
+    "NeedsLogin" => "Login required",
+    "NeedsMachineAuth" => "Waiting for approval",
+    "Starting" => "Starting",
+    _ => "Disconnected",
+  }
+}
+
+/// Checks that `template` only uses known placeholders
+pub fn validate(template: &str) -> Result<(), String> {
+  for piece in split(template) {
+    if let Piece::Placeholder(name) = piece
+      && !PLACEHOLDERS.contains(&name)
+    {
+      return Err(format!(
+        "unknown placeholder {{{name}}}, expected one of {}",
+        PLACEHOLDERS.join(", ")
+      ));
+    }
+  }
+  Ok(())
+}
+
+/// Fills in `template` from `status`, leaving out lines whose placeholders
+/// are all empty
+pub fn render(template: &str, status: &Status, now: Timestamp) -> String {
+  template
+    .lines()
+    .filter_map(|line| {
+      let mut text = String::new();
+      let (mut placeholders, mut filled) = (0, 0);
+      for piece in split(line) {
+        match piece {
+          Piece::Text(part) => text.push_str(part),
+          Piece::Placeholder(name) => {
+            let value = value(status, name, now);
+            placeholders += 1;
+            if !value.is_empty() {
+              filled += 1;
+            }
+            text.push_str(&value);
+          },
+        }
+      }
+      (placeholders == 0 || filled > 0).then_some(text)
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Returns the value of the placeholder `name`, empty if unknown or unset
+fn value(status: &Status, name: &str, now: Timestamp) -> String {
+  let this = &status.this_machine;
+  let ips = |v4: bool| {
+    this
+      .ips
+      .iter()
+      .filter(|ip| ip.is_ipv4() == v4)
+      .map(ToString::to_string)
+      .collect::<Vec<_>>()
+      .join(", ")
+  };
+
+  match name {
+    "state" => state_label(status).to_owned(),
+    "tailnet" => status.tailnet_name().unwrap_or_default().to_owned(),
+    "device" => this.display_name.to_string(),
+    "dns_name" => this.magic_dns_name().unwrap_or_default().to_owned(),
+    "ipv4" => ips(true),
+    "ipv6" => ips(false),
+    "exit_node" => {
+      status
+        .exit_node()
+        .map(|peer| peer.display_name.to_string())
+        .unwrap_or_default()
+    },
+    "peers_online" => {
+      status
+        .peers
+        .values()
+        .filter(|peer| peer.online)
+        .count()
+        .to_string()
+    },
+    "peers_total" => status.peers.len().to_string(),
+    "user" => {
+      status
+        .user_name(this.user_id)
+        .unwrap_or_default()
+        .to_owned()
+    },
+    "key_expiry" => {
+      this
+        .key_expiry
+        .map(|expiry| countdown(expiry, now))
+        .unwrap_or_default()
+    },
+    "health" => {
+      status
+        .health()
+        .iter()
+        .map(|warning| format!("Warning: {warning}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+    },
+    _ => String::new(),
+  }
+}
+
+/// Describes how long until `expiry`, or since it passed
+pub fn countdown(expiry: Timestamp, now: Timestamp) -> String {
+  let remaining = expiry.duration_since(now);
+  if remaining.is_negative() {
+    format!("expired {} ago", approximate(remaining))
+  } else {
+    format!("in {}", approximate(remaining))
+  }
+}
+
+/// Rounds `duration` down to whole days, hours or minutes
+fn approximate(duration: SignedDuration) -> String {
+  let (value, unit) = match duration.as_secs().unsigned_abs() {
+    secs if secs >= 86_400 => (secs / 86_400, "day"),
+    secs if secs >= 3_600 => (secs / 3_600, "hour"),
+    secs => ((secs / 60).max(1), "minute"),
+  };
+
+  if value == 1 {
+    format!("1 {unit}")
+  } else {
+    format!("{value} {unit}s")
+  }
+}
+
+/// Splits `text` into literal text and `{placeholder}`s, where placeholder
+/// names are made of lowercase letters, digits and underscores
+fn split(text: &str) -> Vec<Piece<'_>> {
+  let mut pieces = Vec::new();
+  let mut rest = text;
+  let mut literal = 0;
+
+  while let Some(open) = rest[literal..].find('{').map(|i| literal + i) {
+    let name_len = rest[open + 1..]
+      .find(|c: char| {
+        !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+      })
+      .unwrap_or(rest.len() - open - 1);
+    let close = open + 1 + name_len;
+
+    if name_len == 0 || !rest[close..].starts_with('}') {
+      // Not a placeholder, keep the brace as text
+      literal = open + 1;
+      continue;
+    }
+
+    if open > 0 {
+      pieces.push(Piece::Text(&rest[..open]));
+    }
+    pieces.push(Piece::Placeholder(&rest[open + 1..close]));
+    rest = &rest[close + 1..];
+    literal = 0;
+  }
+
+  if !rest.is_empty() {
+    pieces.push(Piece::Text(rest));
+  }
+  pieces
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tailscale::status;
+
+  const STATUS_JSON: &str = include_str!("../tailscale/testdata/status.json");
+
+  #[test]
+  fn default_template_describes_the_tailnet() {
+    let status = status::parse(STATUS_JSON).expect("fixture should parse");
+    let now: Timestamp = "2025-08-26T10:00:00Z".parse().unwrap();
+
+    let tooltip = render(DEFAULT_TEMPLATE, &status, now);
+
+    assert!(
+      tooltip.starts_with("Tailnet: alice@example.com\n"),
+      "{tooltip}"
+    );
+    assert!(tooltip.contains("Device: framework.tail2f4d1.ts.net\n"));
+    assert!(tooltip.contains("IPv4: 100.101.102.103\n"), "{tooltip}");
+    assert!(tooltip.contains("Exit node: nas\n"));
+    assert!(tooltip.contains("Key expiry: in 3 days\n"), "{tooltip}");
+    assert!(tooltip.ends_with("--accept-routes is false"), "{tooltip}");
+  }
+
+  #[test]
+  fn lines_with_only_empty_placeholders_are_dropped() {
+    let status = Status::default();
+    let tooltip = render(
+      "{state}\nExit node: {exit_node}\n{health}\nPeers: {peers_total}",
+      &status,
+      Timestamp::UNIX_EPOCH,
+    );
+
+    assert_eq!(tooltip, "Disconnected\nPeers: 0");
+  }
+
+  #[test]
+  fn templates_are_split_and_validated() {
+    assert_eq!(split("{a} {b}c{"), [
+      Piece::Placeholder("a"),
+      Piece::Text(" "),
+      Piece::Placeholder("b"),
+      Piece::Text("c{"),
+    ]);
+    assert_eq!(split("{ x } {IP}"), [Piece::Text("{ x } {IP}")]);
+
+    assert!(validate(DEFAULT_TEMPLATE).is_ok());
+    assert!(validate("{ips}").is_err());
+  }
+
+  #[test]
+  fn countdowns_are_approximate() {
+    let now = Timestamp::UNIX_EPOCH;
+    let later = |secs| Timestamp::from_second(secs).unwrap();
+
+    assert_eq!(countdown(later(90_000), now), "in 1 day");
+    assert_eq!(countdown(later(7_300), now), "in 2 hours");
+    assert_eq!(countdown(later(10), now), "in 1 minute");
+    assert_eq!(countdown(later(-3 * 86_400), now), "expired 3 days ago");
+  }
+}
@@ -1,32 +1,173 @@
 use std::{
   error::Error,
+  hash::{BuildHasher, RandomState},
+  ops::ControlFlow,
   sync::{
     Arc,
+    Mutex,
+    MutexGuard,
+    Once,
+    PoisonError,
     atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, RecvTimeoutError, Sender},
   },
   thread,
-  time::Duration,
+  time::{Duration, Instant},
 };
 
-use ksni::blocking::TrayMethods;
-use log::{error, info};
+use ksni::blocking::{Handle, TrayMethods};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
 
-use crate::{tailscale, tray::menu::SysTray};
+use crate::{
+  appearance,
+  config::{self, Config, ConfigError, deserialize_seconds},
+  dbus::{self, Action},
+  error::AppError,
+  ipc::{InstanceLock, Request},
+  notification::{self, Category},
+  svg::{icons, renderer::Theme},
+  tailscale::{
+    self,
+    localapi::{LocalApi, LocalApiError},
+    watch::{
+      IpnBus,
+      NOTIFY_INITIAL_STATE,
+      NOTIFY_NO_PRIVATE_KEYS,
+      NOTIFY_RATE_LIMIT,
+    },
+  },
+  tray::{
+    alerts,
+    menu::{Context, SysTray},
+  },
+};
 
 type TrayServiceError = Box<dyn Error>;
 
+/// Handle to the running tray, replaced whenever the watchdog respawns it
+type SharedHandle = Arc<Mutex<Option<Handle<SysTray>>>>;
+
+/// How long to wait for a burst of bus notifications to settle
+const UPDATE_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Upper bound on how long a burst may delay a refresh
+const UPDATE_MAX_DELAY: Duration = Duration::from_secs(1);
+
 // Global flag for tracking disconnection
 static NEEDS_RESPAWN: AtomicBool = AtomicBool::new(false);
 
-pub fn start_tray_service() -> Result<(), TrayServiceError> {
+/// Guards against following the desktop's color scheme twice
+static APPEARANCE_WATCHED: Once = Once::new();
+
+/// Settings for the status poller used when the IPN bus cannot be watched
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollerConfig {
+  /// Time between two consecutive polls
+  #[serde(deserialize_with = "deserialize_seconds")]
+  pub interval:    Duration,
+  /// Upper bound of the random delay added to every interval
+  #[serde(deserialize_with = "deserialize_seconds")]
+  pub jitter:      Duration,
+  /// Upper bound of the delay after consecutive failed polls
+  #[serde(deserialize_with = "deserialize_seconds")]
+  pub max_backoff: Duration,
+}
+
+impl Default for PollerConfig {
+  fn default() -> Self {
+    Self {
+      interval:    Duration::from_secs(5),
+      jitter:      Duration::from_secs(1),
+      max_backoff: Duration::from_secs(60),
+    }
+  }
+}
+
+impl PollerConfig {
+  /// Returns the delay before the next poll, growing exponentially with
+  /// `consecutive_failures` like the watchdog's respawn delay
+  ///
+  /// The random jitter is taken from `random`, see [`jitter_source`].
+  pub fn next_delay(&self, consecutive_failures: u32, random: u64) -> Duration {
+    let base = if consecutive_failures == 0 {
+      self.interval
+    } else {
+      self
+        .interval
+        .saturating_mul(2_u32.pow(consecutive_failures.min(4)))
+        .min(self.max_backoff)
+    };
+
+    // Spread polls out a little so they do not line up with other timers
+    let jitter_ms = u64::try_from(self.jitter.as_millis()).unwrap_or(u64::MAX);
+    let jitter = match jitter_ms {
+      0 => 0,
+      max => random % (max + 1),
+    };
+
+    base + Duration::from_millis(jitter)
+  }
+}
+
+/// Returns a random number for [`PollerConfig::next_delay`]
+pub fn jitter_source() -> u64 {
+  RandomState::new().hash_one(Instant::now())
+}
+
+pub fn start_tray_service(lock: InstanceLock) -> Result<(), TrayServiceError> {
   // Get initial status for the tray
   let status = tailscale::status::get_current()
     .map_err(|e| format!("Failed to update Tailscale status: {e}"))?;
 
+  let handle: SharedHandle = Arc::new(Mutex::new(None));
+
+  // Know the desktop's color scheme before the first icon is drawn, and
+  // redraw the icon when it changes
+  watch_appearance(&handle, status.config.theme);
+
+  // Load icons from the user's icon directory and redraw when they change
+  {
+    let handle = handle.clone();
+    if let Err(e) = icons::watch(move || redraw_tray(&handle)) {
+      warn!("Icon changes will require a restart: {e}");
+    }
+  }
+
+  // Carry out buttons clicked on notifications in the tray
+  {
+    let handle = handle.clone();
+    notification::set_handler(move |action| {
+      if let Err(e) = handle_notification_action(&handle, action) {
+        error!("Failed to carry out notification action: {e}");
+      }
+    });
+  }
+
+  // Notify about watched peers changing state from the first status on
+  alerts::start(status.status.clone());
+
   // Initial tray spawning
-  let handle = SysTray { ctx: status }
-    .spawn()
-    .map_err(|e| format!("Failed to spawn Tray implementation: {e}"))?;
+  let tray = SysTray {
+    ctx: status.clone(),
+  }
+  .spawn()
+  .map_err(|e| format!("Failed to spawn Tray implementation: {e}"))?;
+  *lock_handle(&handle) = Some(tray);
+
+  // Let other applications control the tray over D-Bus
+  {
+    let handle = handle.clone();
+    if let Err(e) =
+      dbus::start(&status, move |action| handle_action(&handle, action))
+    {
+      warn!(
+        "Failed to register {} on the session bus: {e}",
+        dbus::BUS_NAME
+      );
+    }
+  }
 
   // Flag to control application lifecycle
   let running = Arc::new(AtomicBool::new(true));
@@ -44,22 +185,29 @@ pub fn start_tray_service() -> Result<(), TrayServiceError> {
   // Start watchdog thread for spawning new trays if status bar restarts
   {
     let running = running.clone();
+    let handle = handle.clone();
     thread::spawn(move || {
       info!("Started watchdog thread for status bar monitoring");
       let mut consecutive_failures = 0;
-      let mut handle = Some(handle);
 
       while running.load(Ordering::SeqCst) {
-        if NEEDS_RESPAWN.load(Ordering::SeqCst) || handle.is_none() {
+        let missing = lock_handle(&handle).is_none();
+        if NEEDS_RESPAWN.load(Ordering::SeqCst) || missing {
           info!("Tray disconnected or missing, attempting to respawn...");
           NEEDS_RESPAWN.store(false, Ordering::SeqCst);
 
           match tailscale::status::get_current().and_then(|ctx| {
-            SysTray { ctx }.spawn().map_err(std::convert::Into::into)
+            // The state may have changed while the tray was gone, so
+            // publish it like any other update
+            let mut tray = SysTray {
+              ctx: Context::default(),
+            };
+            tray.set_context(ctx);
+            tray.spawn().map_err(std::convert::Into::into)
           }) {
             Ok(new_handle) => {
               info!("Successfully respawned tray icon");
-              handle = Some(new_handle);
+              *lock_handle(&handle) = Some(new_handle);
               consecutive_failures = 0;
             },
             Err(e) => {
@@ -71,8 +219,7 @@ pub fn start_tray_service() -> Result<(), TrayServiceError> {
           }
 
           if consecutive_failures > 0 {
-            let delay = (2_u64.pow(consecutive_failures.min(4))).min(30);
-            thread::sleep(Duration::from_secs(delay));
+            thread::sleep(backoff_delay(consecutive_failures));
           }
         }
         thread::sleep(Duration::from_secs(2));
@@ -81,6 +228,28 @@ pub fn start_tray_service() -> Result<(), TrayServiceError> {
     });
   }
 
+  // Apply edits to the configuration file without a restart
+  {
+    let handle = handle.clone();
+    if let Err(e) = config::watch(move |result| apply_config(&handle, result)) {
+      warn!("Configuration changes will require a restart: {e}");
+    }
+  }
+
+  // Carry out commands forwarded by later invocations
+  {
+    let handle = handle.clone();
+    let running = running.clone();
+    if let Err(e) =
+      lock.serve(move |request| handle_request(&handle, &running, request))
+    {
+      warn!("Other invocations will not be able to reach the tray: {e}");
+    }
+  }
+
+  // Keep the tray in sync with changes made outside of Tailray
+  spawn_status_watcher(handle);
+
   // Keep the main thread alive until shutdown is requested
   while running.load(Ordering::SeqCst) {
     thread::sleep(Duration::from_millis(100));
@@ -94,3 +263,447 @@ pub fn start_tray_service() -> Result<(), TrayServiceError> {
 pub fn signal_respawn_needed() {
   NEEDS_RESPAWN.store(true, Ordering::SeqCst);
 }
+
+/// Exponential delay used when retrying after consecutive failures
+fn backoff_delay(consecutive_failures: u32) -> Duration {
+  Duration::from_secs((2_u64.pow(consecutive_failures.min(4))).min(30))
+}
+
+/// Locks the shared handle, recovering it if a holder panicked
+fn lock_handle(
+  handle: &SharedHandle,
+) -> MutexGuard<'_, Option<Handle<SysTray>>> {
+  handle.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Subscribes to the IPN bus and refreshes the tray whenever the backend
+/// state, network map, prefs or health change, falling back to polling when
+/// the bus cannot be watched
+fn spawn_status_watcher(handle: SharedHandle) {
+  let (tx, rx) = mpsc::channel();
+
+  {
+    let handle = handle.clone();
+    thread::spawn(move || {
+      watch_ipn_bus(&LocalApi::default(), tx);
+      poll_status(&handle);
+    });
+  }
+  thread::spawn(move || apply_status_updates(&rx, &handle));
+}
+
+/// Forwards relevant IPN bus notifications from `api` to `tx`, reconnecting
+/// whenever the stream drops
+///
+/// Returns once the bus turns out to be unavailable, either because
+/// tailscaled rejects the request or because its socket could never be
+/// reached.
+pub fn watch_ipn_bus(api: &LocalApi, tx: Sender<()>) {
+  let mask = NOTIFY_INITIAL_STATE | NOTIFY_NO_PRIVATE_KEYS | NOTIFY_RATE_LIMIT;
+  let mut consecutive_failures = 0;
+  let mut connected_once = false;
+
+  loop {
+    match IpnBus::watch(api, mask) {
+      Ok(bus) => {
+        info!("Watching IPN bus for state changes");
+        consecutive_failures = 0;
+        connected_once = true;
+
+        for notify in bus {
+          match notify {
+            Ok(notify) => {
+              if let Some(msg) = &notify.err_message {
+                warn!("tailscaled reported an error: {msg}");
+              }
+
+              if notify.changes_status() {
+                debug!("IPN bus notification (state: {:?})", notify.state);
+                if tx.send(()).is_err() {
+                  return;
+                }
+              }
+            },
+            Err(e) => {
+              warn!("IPN bus stream failed: {e}");
+              break;
+            },
+          }
+        }
+        info!("IPN bus closed, reconnecting...");
+      },
+      Err(e @ LocalApiError::Http { .. }) => {
+        warn!("IPN bus rejected by tailscaled ({e}), falling back to polling");
+        return;
+      },
+      Err(e @ LocalApiError::Connect(_)) if !connected_once => {
+        warn!("IPN bus unreachable ({e}), falling back to polling");
+        return;
+      },
+      Err(e) => {
+        consecutive_failures += 1;
+        error!("Failed to watch IPN bus (attempt {consecutive_failures}): {e}");
+      },
+    }
+
+    thread::sleep(backoff_delay(consecutive_failures.max(1)));
+  }
+}
+
+/// Refreshes the tray once per burst of notifications received on `rx`
+fn apply_status_updates(rx: &Receiver<()>, handle: &SharedHandle) {
+  for_each_burst(rx, || {
+    match tailscale::status::get_current() {
+      Ok(ctx) => update_tray(handle, ctx),
+      Err(e) => warn!("Failed to refresh status after IPN bus update: {e}"),
+    }
+    ControlFlow::Continue(())
+  });
+}
+
+/// Calls `apply` once per burst of notifications received on `rx`, until
+/// the sender goes away or `apply` breaks
+pub fn for_each_burst(
+  rx: &Receiver<()>,
+  mut apply: impl FnMut() -> ControlFlow<()>,
+) {
+  while rx.recv().is_ok() {
+    let deadline = Instant::now() + UPDATE_MAX_DELAY;
+    loop {
+      let wait = deadline
+        .saturating_duration_since(Instant::now())
+        .min(UPDATE_DEBOUNCE);
+      match rx.recv_timeout(wait) {
+        Ok(()) if Instant::now() < deadline => {},
+        Ok(()) | Err(RecvTimeoutError::Timeout) => break,
+        Err(RecvTimeoutError::Disconnected) => return,
+      }
+    }
+
+    if apply().is_break() {
+      return;
+    }
+  }
+}
+
+/// Periodically re-reads the status, updating the tray only when something
+/// visible changed
+///
+/// The poller settings are re-read before every poll so that a reloaded
+/// configuration takes effect immediately.
+fn poll_status(handle: &SharedHandle) {
+  info!(
+    "Polling Tailscale status every {:?}",
+    config::current().refresh.interval
+  );
+  let mut previous: Option<Context> = None;
+  let mut consecutive_failures = 0;
+
+  loop {
+    match tailscale::status::get_current() {
+      Ok(ctx) => {
+        consecutive_failures = 0;
+        if previous.as_ref().is_none_or(|prev| ctx.has_changed(prev)) {
+          debug!("Tailscale status changed, updating tray");
+          update_tray(handle, ctx.clone());
+          previous = Some(ctx);
+        }
+      },
+      Err(e) => {
+        consecutive_failures += 1;
+        warn!("Failed to poll status (attempt {consecutive_failures}): {e}");
+      },
+    }
+
+    thread::sleep(
+      config::current()
+        .refresh
+        .next_delay(consecutive_failures, jitter_source()),
+    );
+  }
+}
+
+/// Carries out a request forwarded by another invocation
+fn handle_request(
+  handle: &SharedHandle,
+  running: &AtomicBool,
+  request: Request,
+) -> Result<(), String> {
+  match request {
+    Request::Toggle => with_tray(handle, SysTray::toggle),
+    Request::Refresh => with_tray(handle, SysTray::update_status),
+    Request::Quit => {
+      info!("Received quit request, exiting...");
+      running.store(false, Ordering::SeqCst);
+      Ok(())
+    },
+    Request::ShowMenu => with_tray(handle, |tray| tray.show_summary()),
+  }
+}
+
+/// Carries out an action requested over D-Bus
+fn handle_action(handle: &SharedHandle, action: Action) -> Result<(), String> {
+  match action {
+    Action::Connect => with_tray(handle, |tray| tray.do_service_link("up")),
+    Action::Disconnect => {
+      with_tray(handle, |tray| tray.do_service_link("down"))
+    },
+    Action::Toggle => with_tray(handle, SysTray::toggle),
+    Action::SetExitNode(name) => {
+      with_tray(handle, move |tray| tray.set_exit_node_by_name(&name))
+    },
+    Action::Refresh => with_tray(handle, SysTray::update_status),
+  }
+}
+
+/// What the buttons on notifications can ask of the tray
+trait NotificationTarget {
+  fn do_service_link(&mut self, verb: &str) -> Result<(), AppError>;
+  fn show_log(&mut self, error: &str) -> Result<(), AppError>;
+  fn reauthenticate(&mut self) -> Result<(), AppError>;
+}
+
+impl NotificationTarget for SysTray {
+  fn do_service_link(&mut self, verb: &str) -> Result<(), AppError> {
+    Self::do_service_link(self, verb)
+  }
+
+  fn show_log(&mut self, error: &str) -> Result<(), AppError> {
+    Self::show_log(self, error)
+  }
+
+  fn reauthenticate(&mut self) -> Result<(), AppError> {
+    Self::reauthenticate(self)
+  }
+}
+
+/// Carries out a button clicked on a notification
+fn handle_notification_action(
+  handle: &SharedHandle,
+  action: notification::Action,
+) -> Result<(), String> {
+  with_tray(handle, move |tray| {
+    dispatch_notification_action(tray, action)
+  })
+}
+
+/// Passes `action` on to the matching method of `target`
+fn dispatch_notification_action(
+  target: &mut impl NotificationTarget,
+  action: notification::Action,
+) -> Result<(), AppError> {
+  match action {
+    notification::Action::ServiceLink(verb) => target.do_service_link(&verb),
+    notification::Action::ShowLog(error) => target.show_log(&error),
+    notification::Action::Reauthenticate => target.reauthenticate(),
+  }
+}
+
+/// Runs `action` on the running tray, describing any failure
+fn with_tray<F>(handle: &SharedHandle, action: F) -> Result<(), String>
+where
+  F: FnOnce(&mut SysTray) -> Result<(), AppError>,
+{
+  // Clone the handle so that slow actions, such as waiting for a password,
+  // do not block the watchdog
+  let tray = lock_handle(handle).clone();
+  tray
+    .and_then(|tray| tray.update(action))
+    .ok_or_else(|| "the tray is not running".to_owned())?
+    .map_err(|e| e.to_string())
+}
+
+/// Pushes a reloaded configuration into the running tray, or tells the user
+/// why the configuration file was rejected
+fn apply_config(
+  handle: &SharedHandle,
+  result: Result<Arc<Config>, ConfigError>,
+) {
+  match result {
+    Ok(config) => {
+      // The icon directory may have moved
+      icons::reload();
+      watch_appearance(handle, config.theme);
+      if let Some(handle) = lock_handle(handle).as_ref() {
+        handle.update(move |tray: &mut SysTray| tray.ctx.config = config);
+      }
+    },
+    Err(e) => {
+      if let Err(e) = notification::show(
+        Category::Config,
+        "Configuration not reloaded",
+        &e.to_string(),
+        "error",
+      ) {
+        warn!("Failed to show notification: {e}");
+      }
+    },
+  }
+}
+
+/// Follows the desktop's color scheme once `theme` is [`Theme::Auto`],
+/// redrawing the tray when the scheme changes
+fn watch_appearance(handle: &SharedHandle, theme: Theme) {
+  if theme != Theme::Auto {
+    return;
+  }
+
+  APPEARANCE_WATCHED.call_once(|| {
+    let handle = handle.clone();
+    appearance::watch(move |_| {
+      // The theme may no longer follow the desktop since the watch started
+      if config::current().theme == Theme::Auto {
+        redraw_tray(&handle);
+      }
+    });
+  });
+}
+
+/// Redraws the running tray, if there is one, to pick up a new color scheme
+/// or icon
+fn redraw_tray(handle: &SharedHandle) {
+  if let Some(handle) = lock_handle(handle).as_ref() {
+    handle.update(|_: &mut SysTray| {});
+  }
+}
+
+/// Replaces the context of the running tray, if there is one
+fn update_tray(handle: &SharedHandle, ctx: Context) {
+  if let Some(handle) = lock_handle(handle).as_ref() {
+    handle.update(move |tray: &mut SysTray| tray.set_context(ctx));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CONFIG: PollerConfig = PollerConfig {
+    interval:    Duration::from_secs(5),
+    jitter:      Duration::from_secs(1),
+    max_backoff: Duration::from_secs(60),
+  };
+
+  #[test]
+  fn failures_back_off_up_to_the_cap() {
+    let delays: Vec<_> = (0..8)
+      .map(|failures| CONFIG.next_delay(failures, 0).as_secs())
+      .collect();
+
+    assert_eq!(delays, [5, 10, 20, 40, 60, 60, 60, 60]);
+  }
+
+  #[test]
+  fn jitter_stays_within_bounds() {
+    let jittered = |random| CONFIG.next_delay(0, random).as_millis();
+
+    assert_eq!(jittered(0), 5000);
+    assert_eq!(jittered(1000), 6000);
+    assert_eq!(jittered(1001), 5000, "jitter should wrap around");
+    assert_eq!(jittered(u64::MAX), 5000 + u128::from(u64::MAX % 1001));
+    assert!(CONFIG.next_delay(7, u64::MAX) <= Duration::from_secs(61));
+
+    let no_jitter = PollerConfig {
+      jitter: Duration::ZERO,
+      ..CONFIG
+    };
+    assert_eq!(no_jitter.next_delay(0, u64::MAX), Duration::from_secs(5));
+  }
+
+  #[test]
+  fn bus_notifications_in_a_burst_are_applied_once() {
+    use std::{
+      io::{BufRead, BufReader, Write},
+      os::unix::net::UnixListener,
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tailscaled.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = thread::spawn(move || {
+      // Stream two notifications, then reject the reconnect so that the
+      // watcher gives up
+      let responses: [&[u8]; 2] = [
+        b"HTTP/1.0 200 OK\r\n\r\n{\"State\":6}\n\n{\"NetMap\":{}}\n",
+        b"HTTP/1.0 403 Forbidden\r\nContent-Length: 0\r\n\r\n",
+      ];
+      let mut requests = Vec::new();
+      for response in responses {
+        let (stream, _) = listener.accept().unwrap();
+        // Read the whole request, or closing the socket would reset it
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        requests.push(line.clone());
+        while reader.read_line(&mut line).unwrap() > 2 {
+          line.clear();
+        }
+        reader.get_mut().write_all(response).unwrap();
+      }
+      requests
+    });
+
+    let (tx, rx) = mpsc::channel();
+    let api = LocalApi::new(path);
+    thread::spawn(move || watch_ipn_bus(&api, tx));
+
+    let mut applied = 0;
+    for_each_burst(&rx, || {
+      applied += 1;
+      ControlFlow::Continue(())
+    });
+
+    assert_eq!(applied, 1);
+    let requests = server.join().unwrap();
+    assert!(
+      requests[0].contains("watch-ipn-bus?mask="),
+      "{:?}",
+      requests[0]
+    );
+  }
+
+  /// Records the calls made by notification buttons
+  #[derive(Default)]
+  struct Recorder(Vec<String>);
+
+  impl NotificationTarget for Recorder {
+    fn do_service_link(&mut self, verb: &str) -> Result<(), AppError> {
+      self.0.push(format!("link {verb}"));
+      Ok(())
+    }
+
+    fn show_log(&mut self, error: &str) -> Result<(), AppError> {
+      self.0.push(format!("log {error}"));
+      Ok(())
+    }
+
+    fn reauthenticate(&mut self) -> Result<(), AppError> {
+      self.0.push("reauthenticate".into());
+      Ok(())
+    }
+  }
+
+  fn dispatched(action: notification::Action) -> Vec<String> {
+    let mut recorder = Recorder::default();
+    dispatch_notification_action(&mut recorder, action).unwrap();
+    recorder.0
+  }
+
+  #[test]
+  fn service_link_buttons_run_the_verb() {
+    let action = notification::Action::ServiceLink("up".into());
+    assert_eq!(dispatched(action), ["link up"]);
+  }
+
+  #[test]
+  fn show_log_buttons_pass_the_error() {
+    let action = notification::Action::ShowLog("permission denied".into());
+    assert_eq!(dispatched(action), ["log permission denied"]);
+  }
+
+  #[test]
+  fn reauthenticate_buttons_start_a_login() {
+    let action = notification::Action::Reauthenticate;
+    assert_eq!(dispatched(action), ["reauthenticate"]);
+  }
+}